@@ -0,0 +1,82 @@
+//! The desktop clipboard for `--for-clipboard` and `--copy-last`, through
+//! whichever of wl-clipboard, xclip, xsel or pbcopy is installed.
+
+use std::env;
+use std::io::{self, Write};
+
+use subprocess::{Exec, NullFile, PopenError, Redirection};
+
+/// Commands reading the clipboard, in order of preference. wl-clipboard
+/// is only tried in a Wayland session.
+const PASTE: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-out"],
+    &["xsel", "--clipboard", "--output"],
+    &["pbpaste"],
+];
+
+/// Commands writing the clipboard, in the same order.
+const COPY: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard", "-in"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+];
+
+fn candidates(commands: &'static [&'static [&'static str]]) -> impl Iterator<Item = &'static [&'static str]> {
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+    commands.iter().cloned().filter(move |command| wayland || !command[0].starts_with("wl-"))
+}
+
+fn not_found(e: &PopenError) -> bool {
+    match e {
+        PopenError::IoError(e) => e.kind() == io::ErrorKind::NotFound,
+        _ => false,
+    }
+}
+
+fn no_tool() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no clipboard tool found, install wl-clipboard, xclip or xsel")
+}
+
+/// The text on the clipboard.
+pub fn paste() -> io::Result<String> {
+    for command in candidates(PASTE) {
+        let result = Exec::cmd(command[0]).args(&command[1..])
+            .stdout(Redirection::Pipe)
+            .stderr(NullFile)
+            .capture();
+        match result {
+            Ok(capture) if capture.success() => return Ok(capture.stdout_str()),
+            Ok(capture) => return Err(io::Error::other(format!("{} failed with {:?}", command[0], capture.exit_status))),
+            Err(ref e) if not_found(e) => continue,
+            Err(e) => return Err(io::Error::other(e.to_string())),
+        }
+    }
+    Err(no_tool())
+}
+
+/// Put `text` on the clipboard.
+pub fn copy(text: &str) -> io::Result<()> {
+    for command in candidates(COPY) {
+        let mut child = match Exec::cmd(command[0]).args(&command[1..])
+            .stdin(Redirection::Pipe)
+            .stdout(NullFile)
+            .stderr(NullFile)
+            .popen()
+        {
+            Ok(child) => child,
+            Err(ref e) if not_found(e) => continue,
+            Err(e) => return Err(io::Error::other(e.to_string())),
+        };
+        // xclip and wl-copy keep serving the selection in the background
+        // once their input is closed.
+        child.stdin.take().expect("stdin is piped").write_all(text.as_bytes())?;
+        let status = child.wait().map_err(|e| io::Error::other(e.to_string()))?;
+        if !status.success() {
+            return Err(io::Error::other(format!("{} failed with {:?}", command[0], status)));
+        }
+        return Ok(());
+    }
+    Err(no_tool())
+}
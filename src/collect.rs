@@ -0,0 +1,83 @@
+//! Artifact collection for `--collect`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Copy every file below `from` whose relative path matches one of
+/// `patterns` into `to`, preserving the directory layout. The directory
+/// `skip`, canonical, isn't walked, so that earlier collections below
+/// `from` aren't collected again.
+pub fn collect(from: &Path, patterns: &[String], to: &Path, skip: &Path) -> io::Result<usize> {
+    let mut collected = 0;
+    let mut pending = vec![from.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                if path.canonicalize()? != skip {
+                    pending.push(path);
+                }
+                continue;
+            }
+            let relative = match path.strip_prefix(from) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            let name = relative.to_string_lossy();
+            if patterns.iter().any(|pattern| glob_match(pattern, &name)) {
+                let target = to.join(relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&path, &target)?;
+                collected += 1;
+            }
+        }
+    }
+    Ok(collected)
+}
+
+/// `tag` as a single path component: without separators and not `.` or
+/// `..`.
+pub fn path_component(tag: &str) -> String {
+    let component = tag.replace(['/', '\\'], "_");
+    match component.as_str() {
+        "" | "." | ".." => component.replace('.', "_") + "_",
+        _ => component,
+    }
+}
+
+/// Shell-style matching of a relative path. `*` and `?` do not cross `/`,
+/// `**` matches any number of directories.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_segments(rest, &path[i..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                let segment: Vec<char> = segment.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                match_segment(&segment, &name) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| match_segment(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_segment(rest, &name[1..]),
+    }
+}
@@ -0,0 +1,432 @@
+//! Run specifications read from a config file or stdin, filling in options
+//! the command line leaves unset.
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read};
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use structopt::clap::ArgMatches;
+
+use json::{self, Json};
+
+/// A setting's value, as written in a config.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Text(String),
+    List(Vec<Value>),
+}
+
+/// Settings keyed by long option name, plus `command` for the command to
+/// loop.
+pub type Settings = Vec<(String, Value)>;
+
+/// How an option is given on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Flag,
+    Single,
+    /// Repeatable, taking this many values per occurrence.
+    Multiple(usize),
+}
+
+/// The options settings can be given for: their long name, their argument
+/// name and how they are given.
+pub type Options = [(&'static str, &'static str, Kind)];
+
+/// Read the settings of `--config-stdin` and `--config`, in that order of
+/// precedence.
+pub fn load(file: Option<&Path>, stdin: bool) -> Result<Settings, String> {
+    let mut settings = Vec::new();
+    if stdin {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).map_err(|e| format!("failed to read stdin: {}", e))?;
+        settings.extend(parse(&input)?);
+    }
+    if let Some(file) = file {
+        let input = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+        settings.extend(parse(&input).map_err(|e| format!("{}: {}", file.display(), e))?);
+    }
+    Ok(settings)
+}
+
+/// Options whose `LOOP_<OPTION>` variable loop exports to commands, ex.
+/// `$LOOP_SEED`, so that nested loops don't take it for a default.
+const EXPORTED: &[&str] = &["seed", "tag"];
+
+/// Settings from `LOOP_<OPTION>` environment variables, ex. `LOOP_EVERY=5s`
+/// for `--every`, except for the `EXPORTED` ones.
+pub fn from_env(options: &Options) -> Result<Settings, String> {
+    let options = options.iter().filter(|&&(_, _, kind)| kind != Kind::Flag)
+        .chain(options.iter().filter(|&&(_, _, kind)| kind == Kind::Flag));
+    let mut settings = Vec::new();
+    for &(long, _, kind) in options {
+        if NOT_CONFIGURATION.contains(&long) || EXPORTED.contains(&long) {
+            continue;
+        }
+        let var = format!("LOOP_{}", long.to_uppercase().replace('-', "_"));
+        let value = match env::var(&var) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        let value = if kind == Kind::Flag {
+            match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" => Value::Bool(true),
+                "" | "0" | "false" | "no" => Value::Bool(false),
+                _ => return Err(format!("{} must be true or false", var)),
+            }
+        } else {
+            Value::Text(value)
+        };
+        settings.push((long.to_owned(), value));
+    }
+    Ok(settings)
+}
+
+/// Parse a JSON object or TOML document of settings.
+pub fn parse(input: &str) -> Result<Settings, String> {
+    if input.trim_start().starts_with('{') {
+        parse_json(input)
+    } else {
+        parse_toml(input)
+    }
+}
+
+fn parse_json(input: &str) -> Result<Settings, String> {
+    fn value(key: &str, json: Json) -> Result<Option<Value>, String> {
+        Ok(match json {
+            Json::Null => None,
+            Json::Bool(b) => Some(Value::Bool(b)),
+            Json::Number(n) | Json::String(n) => Some(Value::Text(n)),
+            Json::Array(values) => {
+                let mut list = Vec::new();
+                for v in values {
+                    list.extend(value(key, v)?);
+                }
+                Some(Value::List(list))
+            }
+            Json::Object(_) => return Err(format!("'{}' can't be an object", key)),
+        })
+    }
+
+    match Json::parse(input)? {
+        Json::Object(members) => {
+            let mut settings = Vec::new();
+            for (key, json) in members {
+                if let Some(v) = value(&key, json)? {
+                    settings.push((key, v));
+                }
+            }
+            Ok(settings)
+        }
+        _ => Err("expected a JSON object".to_owned()),
+    }
+}
+
+/// Parse the subset of TOML needed for settings: `key = value` lines of
+/// strings, numbers, booleans and single-line arrays.
+fn parse_toml(input: &str) -> Result<Settings, String> {
+    let mut settings = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |e: String| format!("line {}: {}", number + 1, e);
+        if line.starts_with('[') {
+            return Err(error("tables are not supported".to_owned()));
+        }
+        let eq = line.find('=').ok_or_else(|| error("expected 'key = value'".to_owned()))?;
+        let key = line[..eq].trim().trim_matches('"').to_owned();
+        let mut chars = line[eq + 1..].chars().peekable();
+        let value = toml_value(&mut chars).map_err(error)?;
+        skip_spaces(&mut chars);
+        match chars.next() {
+            None | Some('#') => settings.push((key, value)),
+            Some(c) => return Err(error(format!("unexpected '{}' after value", c))),
+        }
+    }
+    Ok(settings)
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn toml_value(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+    skip_spaces(chars);
+    match chars.peek().cloned() {
+        Some('"') => {
+            // Basic strings share their escapes with JSON.
+            let mut raw = String::new();
+            raw.push(chars.next().unwrap_or('"'));
+            loop {
+                match chars.next() {
+                    Some('\\') => {
+                        raw.push('\\');
+                        raw.extend(chars.next());
+                    }
+                    Some('"') => break,
+                    Some(c) => raw.push(c),
+                    None => return Err("unterminated string".to_owned()),
+                }
+            }
+            raw.push('"');
+            match Json::parse(&raw)? {
+                Json::String(s) => Ok(Value::Text(s)),
+                _ => Err("invalid string".to_owned()),
+            }
+        }
+        Some('\'') => {
+            chars.next();
+            let literal: String = chars.by_ref().take_while(|&c| c != '\'').collect();
+            Ok(Value::Text(literal))
+        }
+        Some('[') => {
+            chars.next();
+            let mut list = Vec::new();
+            loop {
+                skip_spaces(chars);
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Ok(Value::List(list));
+                }
+                list.push(toml_value(chars)?);
+                skip_spaces(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Value::List(list)),
+                    _ => return Err("expected ',' or ']' in array".to_owned()),
+                }
+            }
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ']' || c == '#' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            Ok(match word.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => Value::Text(word),
+            })
+        }
+        None => Err("missing value".to_owned()),
+    }
+}
+
+/// The argument name and kind of the option `--<long>` of `options`.
+fn find_option(options: &Options, long: &str) -> Option<(&'static str, Kind)> {
+    options.iter().find(|&&(l, _, _)| l == long).map(|&(_, name, kind)| (name, kind))
+}
+
+fn scalar(value: &Value) -> Option<&str> {
+    match value {
+        Value::Text(text) => Some(text),
+        _ => None,
+    }
+}
+
+/// The values of a list, with nested lists flattened.
+fn flatten(value: &Value) -> Option<Vec<&str>> {
+    match value {
+        Value::Text(text) => Some(vec![text]),
+        Value::List(values) => values.iter().map(flatten)
+            .collect::<Option<Vec<_>>>()
+            .map(|values| values.concat()),
+        Value::Bool(_) => None,
+    }
+}
+
+/// Repeatable options whose values are lists themselves, ex. `--for`. A
+/// list of values is a single occurrence, a list of lists one per list.
+const LIST_OPTIONS: &[&str] = &["for"];
+
+/// Command line arguments for a single setting. Lists repeat options
+/// which accept multiple occurrences and are comma-separated otherwise.
+fn to_args(long: &str, value: &Value, kind: Kind) -> Result<Vec<String>, String> {
+    if LIST_OPTIONS.contains(&long) {
+        let occurrences = match value {
+            Value::List(values) if values.iter().any(|v| matches!(v, Value::List(_))) => values.iter().collect(),
+            value => vec![value],
+        };
+        return occurrences.into_iter()
+            .map(|value| {
+                let values = flatten(value).ok_or_else(|| format!("'{}' expects a list of values", long))?;
+                Ok(format!("--{}={}", long, values.join(",")))
+            })
+            .collect();
+    }
+    match (value, kind) {
+        (Value::Bool(true), _) => Ok(vec![format!("--{}", long)]),
+        (Value::Bool(false), _) => Ok(vec![]),
+        (_, Kind::Flag) => Err(format!("'{}' expects true or false", long)),
+        (value, kind) => {
+            let values = flatten(value).ok_or_else(|| format!("'{}' expects a list of values", long))?;
+            match kind {
+                Kind::Multiple(1) => Ok(values.iter().map(|v| format!("--{}={}", long, v)).collect()),
+                Kind::Multiple(n) => {
+                    if values.len() % n != 0 {
+                        return Err(format!("'{}' expects {} values per occurrence", long, n));
+                    }
+                    let mut args = Vec::new();
+                    for occurrence in values.chunks(n) {
+                        args.push(format!("--{}", long));
+                        args.extend(occurrence.iter().map(|v| v.to_string()));
+                    }
+                    Ok(args)
+                }
+                _ => Ok(vec![format!("--{}={}", long, values.join(","))]),
+            }
+        }
+    }
+}
+
+/// Extend the command line `args`, parsed as `cli`, with `settings` for
+/// every option it doesn't set itself.
+pub fn merge(options: &Options, cli: &ArgMatches, args: Vec<OsString>, settings: &[(String, Value)])
+    -> Result<Vec<OsString>, String>
+{
+    let mut defaults = Vec::new();
+    let mut command = None;
+    let mut seen = HashSet::new();
+    for (key, value) in settings {
+        let long = key.replace('_', "-");
+        if !seen.insert(long.clone()) {
+            continue;
+        }
+        if long == "command" {
+            command = Some(value);
+            continue;
+        }
+        let (name, kind) = match find_option(options, &long) {
+            Some(option) if !long.starts_with("config") => option,
+            _ => return Err(format!("unknown option '{}'", key)),
+        };
+        if cli.occurrences_of(name) == 0 {
+            defaults.extend(to_args(&long, value, kind)?);
+        }
+    }
+
+    let mut args = args.into_iter();
+    let mut merged: Vec<OsString> = args.next().into_iter().collect();
+    merged.extend(defaults.into_iter().map(OsString::from));
+    merged.extend(args);
+    if let Some(command) = command {
+        if !cli.is_present("input") {
+            if !merged.iter().any(|arg| arg == "--") {
+                merged.push("--".into());
+            }
+            let words = match command {
+                Value::Text(command) => vec![command.as_str()],
+                Value::List(words) => words.iter().map(scalar).collect::<Option<_>>()
+                    .ok_or("'command' must be a string or a list of strings")?,
+                Value::Bool(_) => return Err("'command' must be a string or a list of strings".to_owned()),
+            };
+            merged.extend(words.into_iter().map(OsString::from));
+        }
+    }
+    Ok(merged)
+}
+
+/// Options whose values are not part of a run's configuration.
+const NOT_CONFIGURATION: &[&str] = &["help", "version", "config", "config-stdin", "print-config"];
+
+/// Every option `matches` resolves to a value, including defaults, as
+/// settings of `options`.
+pub fn resolve(options: &Options, matches: &ArgMatches) -> Settings {
+    let mut settings = Vec::new();
+    for &(long, name, kind) in options {
+        if NOT_CONFIGURATION.contains(&long) || kind == Kind::Flag {
+            continue;
+        }
+        let values: Vec<Value> = match matches.values_of(name) {
+            Some(values) => values.map(|v| Value::Text(v.to_owned())).collect(),
+            None if matches.is_present(name) => vec![Value::Bool(true)],
+            None => continue,
+        };
+        let value = if LIST_OPTIONS.contains(&long) && values.len() > 1 {
+            Value::List(values.into_iter().map(|value| Value::List(vec![value])).collect())
+        } else if matches!(kind, Kind::Multiple(_)) && !LIST_OPTIONS.contains(&long) {
+            Value::List(values)
+        } else {
+            values.into_iter().next().unwrap_or(Value::Bool(true))
+        };
+        settings.push((long.to_owned(), value));
+    }
+    for &(long, name, kind) in options {
+        if kind == Kind::Flag && !NOT_CONFIGURATION.contains(&long) && matches.is_present(name) {
+            settings.push((long.to_owned(), Value::Bool(true)));
+        }
+    }
+    if let Some(input) = matches.values_of("input") {
+        settings.push(("command".to_owned(), Value::List(input.map(|w| Value::Text(w.to_owned())).collect())));
+    }
+    settings
+}
+
+/// The keys of the settings which `new` sets differently than `old`, or
+/// only one of them sets.
+pub fn changed<'a>(old: &'a [(String, Value)], new: &'a [(String, Value)]) -> Vec<&'a str> {
+    let value = |settings: &'a [(String, Value)], key: &str| settings.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    let added = new.iter().filter(|(key, _)| value(old, key).is_none());
+    old.iter().chain(added)
+        .map(|(key, _)| key.as_str())
+        .filter(|key| value(old, key) != value(new, key))
+        .collect()
+}
+
+fn toml_value_of(value: &Value) -> String {
+    match value {
+        Value::Bool(b) => b.to_string(),
+        Value::Text(text) if text.parse::<f64>().is_ok() => text.clone(),
+        Value::Text(text) => json::quote(text),
+        Value::List(values) => format!("[{}]", values.iter().map(toml_value_of).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+/// `settings` as a TOML document which `--config` reads back.
+pub fn to_toml(settings: &[(String, Value)]) -> String {
+    settings.iter().map(|(key, value)| format!("{} = {}\n", key, toml_value_of(value))).collect()
+}
+
+/// Quote `word` for a POSIX shell if necessary.
+fn shell_quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,@%+".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// `settings` as an equivalent `loop` invocation.
+pub fn to_command_line(options: &Options, settings: &[(String, Value)]) -> String {
+    let mut words = vec!["loop".to_owned()];
+    let mut command = Vec::new();
+    for (key, value) in settings {
+        if key == "command" {
+            if let Value::List(values) = value {
+                command.extend(values.iter().filter_map(scalar).map(String::from));
+            }
+        } else if let Some((_, kind)) = find_option(options, key) {
+            words.extend(to_args(key, value, kind).unwrap_or_default());
+        }
+    }
+    if !command.is_empty() {
+        words.push("--".to_owned());
+        words.extend(command);
+    }
+    words.iter().map(|w| shell_quote(w)).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,124 @@
+//! `--cron`: iterations scheduled by a crontab expression, in local time.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use time::{local_time, MONTHS, WEEKDAYS};
+
+/// Days searched for the next fire time before giving up, as some
+/// expressions never match, ex. February 30th.
+const MAX_DAYS: u32 = 366 * 5;
+
+/// The minutes, hours, days of the month, months and weekdays matched, as
+/// bit sets.
+#[derive(Debug)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the days of the month and of the week are both restricted,
+    /// so that either matching is enough.
+    either_day: bool,
+}
+
+/// Parse five fields, `MINUTE HOUR DAY MONTH WEEKDAY`, ex. `*/5 * * * *`,
+/// or one of `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`.
+pub fn parse(input: &str) -> Result<Schedule, String> {
+    let expanded = match input.trim() {
+        "@hourly" => "0 * * * *",
+        "@daily" | "@midnight" => "0 0 * * *",
+        "@weekly" => "0 0 * * 0",
+        "@monthly" => "0 0 1 * *",
+        "@yearly" | "@annually" => "0 0 1 1 *",
+        fields => fields,
+    }.to_lowercase();
+    let fields: Vec<&str> = expanded.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!("expected MINUTE HOUR DAY MONTH WEEKDAY, ex. '*/5 * * * *', got '{}'", input));
+    }
+    let mut weekdays = field(fields[4], 0, 7, &WEEKDAYS, 0)?;
+    // Sunday is 0 or 7
+    if weekdays & 1 << 7 != 0 {
+        weekdays = (weekdays | 1) & !(1 << 7);
+    }
+    let schedule = Schedule {
+        minutes: field(fields[0], 0, 59, &[], 0)?,
+        hours: field(fields[1], 0, 23, &[], 0)?,
+        days: field(fields[2], 1, 31, &[], 0)?,
+        months: field(fields[3], 1, 12, &MONTHS, 1)?,
+        weekdays,
+        either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+    };
+    match schedule.next(SystemTime::now()) {
+        Some(_) => Ok(schedule),
+        None => Err(format!("'{}' never fires", input)),
+    }
+}
+
+/// Parse a comma separated list of values, `*`, ranges `A-B` and steps
+/// `*/N`, `A-B/N` or `A/N` into a bit set. Values may be named by the
+/// first three letters of `names`, the first of which is `first`.
+fn field(input: &str, min: u32, max: u32, names: &[&str], first: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid cron field '{}', expected values from {} to {}", input, min, max);
+    let value = |text: &str| -> Result<u32, String> {
+        let value = match names.iter().position(|name| text.len() == 3 && name.starts_with(text)) {
+            Some(index) => index as u32 + first,
+            None => text.parse().map_err(|_| invalid())?,
+        };
+        if value < min || value > max {
+            return Err(invalid());
+        }
+        Ok(value)
+    };
+    let mut set = 0;
+    for part in input.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok().filter(|&step| step > 0).ok_or_else(invalid)?)),
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for bit in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << bit;
+        }
+    }
+    Ok(set)
+}
+
+impl Schedule {
+    /// The first minute after `after` matching the schedule.
+    pub fn next(&self, after: SystemTime) -> Option<SystemTime> {
+        let secs = after.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut time = UNIX_EPOCH + Duration::from_secs(secs - secs % 60 + 60);
+        let mut days = 0;
+        while days < MAX_DAYS {
+            let tm = local_time(time);
+            let day_matches = if self.either_day {
+                self.days & 1 << tm.tm_mday != 0 || self.weekdays & 1 << tm.tm_wday != 0
+            } else {
+                self.days & 1 << tm.tm_mday != 0 && self.weekdays & 1 << tm.tm_wday != 0
+            };
+            // Skip the rest of the day or hour when they don't match
+            let skip = if self.months & 1 << (tm.tm_mon + 1) == 0 || !day_matches {
+                days += 1;
+                (24 - tm.tm_hour as u64) * 3600 - tm.tm_min as u64 * 60
+            } else if self.hours & 1 << tm.tm_hour == 0 {
+                (60 - tm.tm_min as u64) * 60
+            } else if self.minutes & 1 << tm.tm_min == 0 {
+                60
+            } else {
+                return Some(time);
+            };
+            time += Duration::from_secs(skip);
+        }
+        None
+    }
+}
@@ -0,0 +1,48 @@
+//! CSV rows as items for `--for-csv`, following RFC 4180.
+
+/// Parse the records of `input`. Fields may be quoted, with `""` for a
+/// literal quote, and then contain commas and newlines.
+pub fn parse(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            '\n' if quoted => {
+                line += 1;
+                field.push(c);
+            }
+            c if quoted => field.push(c),
+            ',' => row.push(field.split_off(0)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                row.push(field.split_off(0));
+                rows.push(row.split_off(0));
+            }
+            '"' => return Err(format!("line {}: unexpected quote in unquoted field", line)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("line {}: unterminated quoted field", line));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
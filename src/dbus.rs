@@ -0,0 +1,111 @@
+//! D-Bus signals triggering iterations with `--on-dbus`, as reported by
+//! `dbus-monitor`.
+
+use std::io::{self, BufRead, BufReader};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use subprocess::{Exec, NullFile, Redirection};
+
+use items::{Item, Queue};
+
+/// `dbus-monitor` prints the arguments of a signal after its header; a
+/// signal is complete once nothing followed for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// A signal being read: its name and arguments.
+struct Signal {
+    name: String,
+    arguments: Vec<String>,
+}
+
+impl Signal {
+    /// Parse the header of a message, ex. `signal time=.. sender=:1.3 ->
+    /// destination=(null destination) serial=.. path=/org/..;
+    /// interface=org.freedesktop.login1.Manager; member=PrepareForSleep`.
+    /// Signals sent to the monitor itself are skipped.
+    fn parse(header: &str) -> Option<Signal> {
+        if !header.starts_with("signal ") || !header.contains("destination=(null destination)") {
+            return None;
+        }
+        let field = |name: &str| {
+            header.split_whitespace()
+                .find_map(|word| word.strip_prefix(name))
+                .map(|value| value.trim_end_matches(';').to_owned())
+        };
+        let name = match (field("interface="), field("member=")) {
+            (Some(interface), Some(member)) => format!("{}.{}", interface, member),
+            (None, Some(member)) => member,
+            _ => return None,
+        };
+        Some(Signal { name, arguments: Vec::new() })
+    }
+
+    /// The item of the signal: its name followed by its arguments, ex.
+    /// `org.freedesktop.login1.Manager.PrepareForSleep true`.
+    fn into_item(self) -> Item {
+        let mut item = self.name;
+        for argument in self.arguments {
+            item.push(' ');
+            item.push_str(&argument);
+        }
+        Item::new(item)
+    }
+}
+
+/// Watch the system bus, or the session bus, for signals matching `rule`
+/// from a thread, pushing every one into `queue`.
+pub fn watch(rule: &str, session: bool, queue: Arc<Queue>) -> io::Result<()> {
+    let mut monitor = Exec::cmd("dbus-monitor")
+        .arg(if session { "--session" } else { "--system" })
+        .arg(rule)
+        .stdout(Redirection::Pipe)
+        .stderr(NullFile)
+        .popen()
+        .map_err(|e| io::Error::other(format!("failed to run dbus-monitor: {}", e)))?;
+    let stdout = monitor.stdout.take().expect("stdout is piped");
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() {
+                    break;
+                },
+                Err(_) => break,
+            }
+        }
+    });
+    thread::spawn(move || {
+        let mut signal: Option<Signal> = None;
+        loop {
+            match receiver.recv_timeout(SETTLE_TIME) {
+                // Arguments are indented, ex. `   boolean true`, nested
+                // ones more so.
+                Ok(ref line) if line.starts_with(' ') => if let Some(ref mut signal) = signal {
+                    let argument = line.trim();
+                    let value = argument.split_once(' ').map_or(argument, |(_, value)| value);
+                    signal.arguments.push(value.to_owned());
+                },
+                Ok(line) => {
+                    if let Some(signal) = signal.take() {
+                        queue.push(signal.into_item());
+                    }
+                    signal = Signal::parse(&line);
+                }
+                Err(RecvTimeoutError::Timeout) => if let Some(signal) = signal.take() {
+                    queue.push(signal.into_item());
+                },
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if let Some(signal) = signal {
+            queue.push(signal.into_item());
+        }
+        let _ = monitor.wait();
+        queue.close();
+    });
+    Ok(())
+}
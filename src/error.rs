@@ -0,0 +1,120 @@
+//! Errors ending a loop, each with its own exit status.
+
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::process;
+use std::time::Duration;
+
+use humantime::format_duration;
+use subprocess::ExitStatus;
+
+use json;
+
+#[derive(Debug)]
+pub enum LoopError {
+    /// The command line, a config or a `LOOP_*` variable is invalid.
+    Usage(String),
+    /// The argument of a condition could not be parsed.
+    ConditionParse(String),
+    /// Another loop holds the `--single-instance` lock; its pid.
+    AlreadyRunning(String),
+    /// `--min-free-space` wasn't met with `--on-low-space abort`.
+    LowSpace(String),
+    /// The command could not be started.
+    SpawnFailed(String),
+    /// A `--checkpoint-every` command failed.
+    CheckpointFailed { command: String, status: ExitStatus },
+    /// The output of an iteration is not valid UTF-8.
+    OutputDecode { iteration: usize },
+    /// `--for-duration` elapsed with `--error-duration`.
+    Timeout(Duration),
+    /// The loop received SIGINT.
+    Interrupted,
+    /// Any other I/O failure of the loop itself.
+    Io(String),
+}
+
+impl LoopError {
+    /// Exit status of the loop, following sysexits(3) and the shell where
+    /// they apply.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoopError::Usage(_) => 64,
+            LoopError::ConditionParse(_) => 65,
+            LoopError::Io(_) => 74,
+            LoopError::CheckpointFailed { .. } => 69,
+            LoopError::LowSpace(_) => 73,
+            LoopError::AlreadyRunning(_) => 75,
+            LoopError::OutputDecode { .. } => 76,
+            // same exit code as use of `timeout` shell command
+            LoopError::Timeout(_) => 124,
+            LoopError::SpawnFailed(_) => 126,
+            LoopError::Interrupted => 130,
+        }
+    }
+
+    /// Identifier of the kind of error in JSON output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoopError::Usage(_) => "usage",
+            LoopError::ConditionParse(_) => "condition_parse",
+            LoopError::AlreadyRunning(_) => "already_running",
+            LoopError::LowSpace(_) => "low_space",
+            LoopError::SpawnFailed(_) => "spawn_failed",
+            LoopError::CheckpointFailed { .. } => "checkpoint_failed",
+            LoopError::OutputDecode { .. } => "output_decode",
+            LoopError::Timeout(_) => "timeout",
+            LoopError::Interrupted => "interrupted",
+            LoopError::Io(_) => "io",
+        }
+    }
+
+    /// Print the error to stderr, as text or, with `--errors json`, as a
+    /// JSON object.
+    pub fn report(&self, json: bool) {
+        if json {
+            eprintln!("{{\"error\": {}, \"message\": {}, \"exit_code\": {}}}",
+                      json::quote(self.kind()), json::quote(&self.to_string()), self.exit_code());
+        } else {
+            eprintln!("{}", self);
+        }
+    }
+
+    /// Report the error and exit with its status.
+    pub fn exit(&self, json: bool) -> ! {
+        self.report(json);
+        process::exit(self.exit_code())
+    }
+}
+
+impl fmt::Display for LoopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoopError::Usage(message) => write!(f, "{}", message),
+            LoopError::ConditionParse(message) => write!(f, "Invalid condition: {}", message),
+            LoopError::AlreadyRunning(pid) =>
+                write!(f, "Another loop is already running this command (pid {})", pid),
+            LoopError::LowSpace(reason) => write!(f, "Aborting: {}", reason),
+            LoopError::SpawnFailed(message) => write!(f, "Failed to run command: {}", message),
+            LoopError::CheckpointFailed { command, status } => match status {
+                ExitStatus::Exited(code) => write!(f, "Checkpoint '{}' failed with exit code {}", command, code),
+                ExitStatus::Signaled(signal) => write!(f, "Checkpoint '{}' was killed by signal {}", command, signal),
+                status => write!(f, "Checkpoint '{}' failed: {:?}", command, status),
+            },
+            LoopError::OutputDecode { iteration } =>
+                write!(f, "Output of iteration {} is not valid UTF-8", iteration),
+            LoopError::Timeout(duration) => write!(f, "Timed out after {}", format_duration(*duration)),
+            LoopError::Interrupted => write!(f, "Interrupted"),
+            LoopError::Io(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Whether `--errors json` is among `args` or in `$LOOP_ERRORS`, for errors
+/// raised before the options could be parsed.
+pub fn json_requested(args: &[OsString]) -> bool {
+    args.iter().any(|arg| arg == "--errors=json")
+        || args.windows(2).any(|pair| pair[0] == "--errors" && pair[1] == "json")
+        || env::var("LOOP_ERRORS").is_ok_and(|format| format == "json")
+}
@@ -0,0 +1,104 @@
+//! `--bisect-git`: `git bisect` driven by the loop, the command deciding
+//! whether every revision checked out is good.
+
+use std::io;
+
+use subprocess::{Exec, Redirection};
+
+/// The exit code of the command marking a revision untestable, as with
+/// `git bisect run`.
+pub const SKIP_CODE: u32 = 125;
+
+/// `GOOD..BAD`, revisions known to be without and with the change looked for.
+#[derive(Debug)]
+pub struct Range {
+    good: String,
+    bad: String,
+}
+
+pub fn parse_range(input: &str) -> Result<Range, String> {
+    match input.split_once("..") {
+        Some((good, bad)) if !good.trim().is_empty() && !bad.trim().is_empty() =>
+            Ok(Range { good: good.trim().to_owned(), bad: bad.trim().to_owned() }),
+        _ => Err(format!("expected GOOD..BAD, ex. v1.0..HEAD, got '{}'", input)),
+    }
+}
+
+pub enum Verdict {
+    Good,
+    Bad,
+    Skip,
+}
+
+pub struct Bisect {
+    /// The revision checked out to test, until the search is over.
+    revision: Option<String>,
+    /// The first bad commit once found, or why the search ended without.
+    outcome: Option<Result<String, String>>,
+}
+
+fn git(args: &[&str]) -> io::Result<(bool, String)> {
+    let capture = Exec::cmd("git").args(args)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok((capture.success(), capture.stdout_str()))
+}
+
+impl Bisect {
+    /// Start bisecting in the repository of the current directory.
+    pub fn start(range: &Range) -> io::Result<Bisect> {
+        let mut bisect = Bisect { revision: None, outcome: None };
+        bisect.step(&["bisect", "start", &range.bad, &range.good, "--"])?;
+        Ok(bisect)
+    }
+
+    pub fn revision(&self) -> Option<&str> {
+        self.revision.as_deref()
+    }
+
+    pub fn record(&mut self, verdict: Verdict) -> io::Result<()> {
+        let verdict = match verdict {
+            Verdict::Good => "good",
+            Verdict::Bad => "bad",
+            Verdict::Skip => "skip",
+        };
+        self.step(&["bisect", verdict])
+    }
+
+    /// Run a bisect command and pick up the revision it checked out, or
+    /// the end of the search.
+    fn step(&mut self, args: &[&str]) -> io::Result<()> {
+        let (success, output) = git(args)?;
+        if !success {
+            self.revision = None;
+            if args[1] == "start" {
+                let _ = git(&["bisect", "reset"]);
+                return Err(io::Error::other(output.trim().to_owned()));
+            }
+            self.outcome = Some(Err(output.trim().to_owned()));
+            return Ok(());
+        }
+        if let Some(line) = output.lines().find(|line| line.ends_with(" is the first bad commit")) {
+            self.revision = None;
+            let commit = line.split_whitespace().next().unwrap_or_default();
+            let (_, described) = git(&["log", "-1", "--format=%H %s", commit])?;
+            self.outcome = Some(Ok(described.trim().to_owned()));
+        } else {
+            let (_, head) = git(&["rev-parse", "HEAD"])?;
+            self.revision = Some(head.trim().to_owned());
+        }
+        Ok(())
+    }
+
+    /// End bisecting, checking out the revision from before, and return the
+    /// outcome.
+    pub fn finish(self) -> io::Result<Option<Result<String, String>>> {
+        let (success, output) = git(&["bisect", "reset"])?;
+        if !success {
+            return Err(io::Error::other(output.trim().to_owned()));
+        }
+        Ok(self.outcome)
+    }
+}
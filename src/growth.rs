@@ -0,0 +1,93 @@
+//! `--until-file-size` and `--until-file-lines`: conditions on the growth
+//! of a file, ex. a log or an output artifact.
+
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Bytes,
+    Lines,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+    Equal,
+    /// Reached the value, or stopped growing toward it since the previous
+    /// iteration.
+    Stalls,
+}
+
+#[derive(Debug)]
+pub struct FileCondition {
+    path: PathBuf,
+    metric: Metric,
+    comparison: Comparison,
+    value: u64,
+    previous: Option<u64>,
+}
+
+/// Parse the PATH, OP and value of a condition on `metric`, where OP is one
+/// of `<`, `<=`, `>`, `>=`, `==` or `stalls`.
+pub fn parse(metric: Metric, values: &[String]) -> Result<FileCondition, String> {
+    let (path, comparison, value) = match values {
+        [path, comparison, value] => (path, comparison, value),
+        _ => return Err(format!("expected PATH OP VALUE, got '{}'", values.join(" "))),
+    };
+    let comparison = match comparison.as_str() {
+        ">" => Comparison::Above,
+        ">=" => Comparison::AtLeast,
+        "<" => Comparison::Below,
+        "<=" => Comparison::AtMost,
+        "==" | "=" => Comparison::Equal,
+        "stalls" => Comparison::Stalls,
+        _ => return Err(format!("expected one of <, <=, >, >=, == or stalls, got '{}'", comparison)),
+    };
+    let value = match metric {
+        Metric::Bytes => ::parse_size(value)?,
+        Metric::Lines => value.parse().map_err(|_| format!("invalid line count '{}'", value))?,
+    };
+    Ok(FileCondition { path: PathBuf::from(path), metric, comparison, value, previous: None })
+}
+
+impl FileCondition {
+    fn measure(&self) -> Option<u64> {
+        match self.metric {
+            Metric::Bytes => fs::metadata(&self.path).ok().map(|metadata| metadata.len()),
+            Metric::Lines => fs::read(&self.path).ok()
+                .map(|contents| contents.iter().filter(|&&b| b == b'\n').count() as u64),
+        }
+    }
+
+    /// Measure the file, describing why the condition holds if it does. A
+    /// missing file holds no condition.
+    pub fn check(&mut self) -> Option<String> {
+        let current = self.measure();
+        let previous = self.previous.take();
+        self.previous = current;
+        let current = current?;
+        let unit = match self.metric {
+            Metric::Bytes => "bytes",
+            Metric::Lines => "lines",
+        };
+        let holds = match self.comparison {
+            Comparison::Above => current > self.value,
+            Comparison::AtLeast => current >= self.value,
+            Comparison::Below => current < self.value,
+            Comparison::AtMost => current <= self.value,
+            Comparison::Equal => current == self.value,
+            Comparison::Stalls if current < self.value && previous == Some(current) =>
+                return Some(format!("{} stopped growing at {} {}", self.path.display(), current, unit)),
+            Comparison::Stalls => current >= self.value,
+        };
+        if holds {
+            Some(format!("{} has {} {}", self.path.display(), current, unit))
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! Guards which hold back the next iteration while the system is not in a
+//! state to run it.
+
+use std::f64;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+use subprocess::{Exec, Redirection};
+
+use signal;
+
+/// How often a paused loop re-checks its guards.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Block while `check` reports a reason to hold back, polling periodically.
+/// The pause and the resume are reported once on stderr.
+pub fn wait_while<F: FnMut() -> Option<String>>(mut check: F) {
+    let mut paused = false;
+    while !signal::interrupted() {
+        let reason = match check() {
+            Some(reason) => reason,
+            None => break,
+        };
+        if !paused {
+            eprintln!("Paused: {}", reason);
+            paused = true;
+        }
+        signal::sleep(POLL_INTERVAL);
+    }
+    if paused {
+        eprintln!("Resumed");
+    }
+}
+
+#[derive(Debug)]
+pub struct FreeSpace {
+    pub bytes: u64,
+    pub path: PathBuf,
+}
+
+impl FreeSpace {
+    /// Describe the shortage if less than the required space is available.
+    pub fn check(&self) -> Option<String> {
+        match free_space(&self.path) {
+            Ok(free) if free < self.bytes => Some(format!(
+                "{} bytes free at {}, below the minimum of {}",
+                free, self.path.display(), self.bytes)),
+            Ok(_) => None,
+            Err(e) => Some(format!("cannot determine free space at {}: {}",
+                                   self.path.display(), e)),
+        }
+    }
+}
+
+/// Parse `SIZE[:PATH]`, defaulting to the current directory.
+pub fn parse_free_space(input: &str) -> Result<FreeSpace, String> {
+    let (size, path) = match input.find(':') {
+        Some(i) => (&input[..i], &input[i + 1..]),
+        None => (input, "."),
+    };
+    Ok(FreeSpace {
+        bytes: ::parse_size(size)?,
+        path: PathBuf::from(path),
+    })
+}
+
+/// The number of bytes available to unprivileged users on the filesystem of `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+/// Describe the excess if the one minute load average is above `max`.
+pub fn check_load(max: f64) -> Option<String> {
+    let mut load = [0f64; 1];
+    if unsafe { libc::getloadavg(load.as_mut_ptr(), 1) } != 1 {
+        return Some("cannot determine the load average".to_owned());
+    }
+    if load[0] > max {
+        Some(format!("load average {:.2} is above {}", load[0], max))
+    } else {
+        None
+    }
+}
+
+/// System-wide CPU usage, measured between consecutive checks.
+#[derive(Debug)]
+pub struct CpuUsage {
+    pub max_percent: f64,
+    last: Option<(u64, u64)>,
+}
+
+impl CpuUsage {
+    pub fn check(&mut self) -> Option<String> {
+        if self.last.is_none() {
+            self.last = read_cpu_times();
+            thread::sleep(Duration::from_millis(250));
+        }
+        let current = read_cpu_times();
+        let usage = match (self.last, current) {
+            (Some((idle, total)), Some((idle_now, total_now))) if total_now > total => {
+                100.0 * (1.0 - (idle_now - idle) as f64 / (total_now - total) as f64)
+            }
+            (_, None) => return Some("cannot determine the CPU usage".to_owned()),
+            _ => 0.0,
+        };
+        self.last = current;
+        if usage > self.max_percent {
+            Some(format!("CPU usage {:.0}% is above {}%", usage, self.max_percent))
+        } else {
+            None
+        }
+    }
+}
+
+/// `--max-cpu`: a usage from 0% to 100%.
+pub fn parse_cpu_usage(input: &str) -> Result<CpuUsage, String> {
+    match parse_percent(input)? {
+        max_percent if (0.0..=100.0).contains(&max_percent) => Ok(CpuUsage { max_percent, last: None }),
+        _ => Err(format!("expected a CPU usage from 0% to 100%, got '{}'", input)),
+    }
+}
+
+/// Idle and total jiffies from the aggregate line of /proc/stat.
+fn read_cpu_times() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let times: Vec<u64> = line.split_whitespace().skip(1)
+        .filter_map(|t| t.parse().ok())
+        .collect();
+    // idle + iowait
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+    Some((idle, times.iter().sum()))
+}
+
+/// Describe why to wait if on battery (when `only_on_ac` is set) or if the
+/// battery charge is below `min_battery` percent. Machines without a
+/// battery always pass.
+pub fn check_power(only_on_ac: bool, min_battery: Option<f64>) -> Option<String> {
+    let power = PowerState::read();
+    if only_on_ac && power.on_ac == Some(false) {
+        return Some("running on battery".to_owned());
+    }
+    match (min_battery, power.battery) {
+        (Some(min), Some(charge)) if charge < min && power.on_ac != Some(true) => Some(
+            format!("battery at {:.0}%, below {}%", charge, min)),
+        _ => None,
+    }
+}
+
+pub fn parse_percent(input: &str) -> Result<f64, String> {
+    input.trim_end_matches('%').parse::<f64>()
+        .map_err(|_| format!("invalid percentage '{}'", input))
+}
+
+/// `--min-battery`: a charge from 0% to 100%.
+pub fn parse_battery(input: &str) -> Result<f64, String> {
+    match parse_percent(input)? {
+        percent if (0.0..=100.0).contains(&percent) => Ok(percent),
+        _ => Err(format!("expected a battery level from 0% to 100%, got '{}'", input)),
+    }
+}
+
+#[derive(Debug, Default)]
+struct PowerState {
+    on_ac: Option<bool>,
+    battery: Option<f64>,
+}
+
+impl PowerState {
+    fn read() -> PowerState {
+        match PowerState::from_sysfs() {
+            Some(state) => state,
+            None => PowerState::from_pmset().unwrap_or_default(),
+        }
+    }
+
+    /// Linux exposes AC adapters and batteries below /sys/class/power_supply.
+    fn from_sysfs() -> Option<PowerState> {
+        let mut state = PowerState::default();
+        let mut charges = Vec::new();
+        for entry in fs::read_dir("/sys/class/power_supply").ok()? {
+            let path = entry.ok()?.path();
+            let read = |name: &str| fs::read_to_string(path.join(name))
+                .map(|s| s.trim().to_owned())
+                .ok();
+            match read("type").as_deref() {
+                Some("Mains") | Some("USB") => {
+                    let online = read("online").as_deref() == Some("1");
+                    state.on_ac = Some(state.on_ac.unwrap_or(false) || online);
+                }
+                Some("Battery") => {
+                    if let Some(capacity) = read("capacity").and_then(|c| c.parse().ok()) {
+                        charges.push(capacity);
+                    }
+                    if state.on_ac.is_none() && read("status").as_deref() == Some("Discharging") {
+                        state.on_ac = Some(false);
+                    }
+                }
+                _ => (),
+            }
+        }
+        if !charges.is_empty() {
+            state.battery = Some(charges.iter().sum::<f64>() / charges.len() as f64);
+        }
+        Some(state)
+    }
+
+    /// macOS reports e.g. "Now drawing from 'AC Power'" and "...; 87%; charging".
+    fn from_pmset() -> Option<PowerState> {
+        let output = Exec::cmd("pmset").args(&["-g", "batt"])
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture().ok()?
+            .stdout_str();
+        let on_ac = output.contains("'AC Power'");
+        let battery = output.split(|c: char| c == ';' || c.is_whitespace())
+            .find(|word| word.ends_with('%'))
+            .and_then(|word| word.trim_end_matches('%').parse().ok());
+        Some(PowerState { on_ac: Some(on_ac), battery })
+    }
+}
+
+/// Probed by --require-network when no host is given.
+pub const DEFAULT_NETWORK_PROBE: &str = "1.1.1.1:53";
+
+/// Describe the failure if no TCP connection can be made to `host`, which
+/// defaults to port 80 when none is given.
+pub fn check_network(host: &str) -> Option<String> {
+    let address = if host.contains(':') { host.to_owned() } else { format!("{}:80", host) };
+    let addrs = match address.to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(e) => return Some(format!("cannot resolve {}: {}", host, e)),
+    };
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, Duration::from_secs(3)) {
+            Ok(_) => return None,
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Some(match last_error {
+        Some(e) => format!("{} is unreachable: {}", host, e),
+        None => format!("{} has no addresses", host),
+    })
+}
+
+/// Describe the excess if the hottest sensor is above `max` degrees Celsius.
+pub fn check_temperature(max: f64) -> Option<String> {
+    let hottest = read_temperatures().into_iter().fold(f64::NAN, f64::max);
+    if hottest > max {
+        Some(format!("temperature {:.0}C is above {}C", hottest, max))
+    } else {
+        None
+    }
+}
+
+/// Parse a temperature such as 85C, 185F or plain 85 (Celsius).
+pub fn parse_temperature(input: &str) -> Result<f64, String> {
+    let invalid = || format!("invalid temperature '{}'", input);
+    let upper = input.trim().to_uppercase();
+    if upper.ends_with('F') {
+        let fahrenheit: f64 = upper.trim_end_matches('F').parse().map_err(|_| invalid())?;
+        Ok((fahrenheit - 32.0) * 5.0 / 9.0)
+    } else {
+        upper.trim_end_matches('C').parse().map_err(|_| invalid())
+    }
+}
+
+/// Readings in degrees Celsius from hwmon, thermal zones and nvidia-smi.
+fn read_temperatures() -> Vec<f64> {
+    let mut temperatures = Vec::new();
+
+    let mut sensor_files = Vec::new();
+    if let Ok(entries) = fs::read_dir("/sys/class/hwmon") {
+        for entry in entries.filter_map(Result::ok) {
+            if let Ok(files) = fs::read_dir(entry.path()) {
+                sensor_files.extend(files.filter_map(Result::ok)
+                    .map(|f| f.path())
+                    .filter(|p| p.file_name().map(|n| n.to_string_lossy())
+                            .is_some_and(|n| n.starts_with("temp") && n.ends_with("_input"))));
+            }
+        }
+    }
+    if let Ok(entries) = fs::read_dir("/sys/class/thermal") {
+        sensor_files.extend(entries.filter_map(Result::ok)
+            .filter(|e| e.file_name().to_string_lossy().starts_with("thermal_zone"))
+            .map(|e| e.path().join("temp")));
+    }
+    for file in sensor_files {
+        // millidegrees Celsius
+        if let Some(millidegrees) = fs::read_to_string(file).ok().and_then(|t| t.trim().parse::<f64>().ok()) {
+            temperatures.push(millidegrees / 1000.0);
+        }
+    }
+
+    if let Ok(output) = Exec::cmd("nvidia-smi")
+        .args(&["--query-gpu=temperature.gpu", "--format=csv,noheader,nounits"])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture() {
+        if output.exit_status.success() {
+            temperatures.extend(output.stdout_str().lines().filter_map(|l| l.trim().parse::<f64>().ok()));
+        }
+    }
+    temperatures
+}
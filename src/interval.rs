@@ -0,0 +1,177 @@
+//! The time the loop waits between iterations, on top of `--every`.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use humantime::parse_duration;
+
+use random::Rng;
+
+/// `--backoff`: the interval grows by a factor with every failure in a row,
+/// up to a maximum, and is back to `--every` after a success.
+#[derive(Debug)]
+pub struct Backoff {
+    factor: f64,
+    max: Duration,
+    failures: u32,
+}
+
+/// The maximum interval of `--backoff` without one given.
+const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// The interval `--backoff` grows from without `--every`.
+const DEFAULT_BACKOFF_BASE: Duration = Duration::from_secs(1);
+
+/// Parse `FACTOR[..MAX]`, ex. `2..5m`.
+pub fn parse_backoff(input: &str) -> Result<Backoff, String> {
+    let invalid = || format!("expected FACTOR[..MAX], ex. 2..5m, got '{}'", input);
+    let (factor, max) = match input.split_once("..") {
+        Some((factor, max)) => (factor, parse_duration(max.trim()).map_err(|_| invalid())?),
+        None => (input, DEFAULT_BACKOFF_MAX),
+    };
+    let factor: f64 = factor.trim().parse().map_err(|_| invalid())?;
+    if !(factor >= 1.0 && factor.is_finite()) {
+        return Err(format!("the factor must be at least 1, got '{}'", input));
+    }
+    Ok(Backoff { factor, max, failures: 0 })
+}
+
+impl Backoff {
+    pub fn record(&mut self, success: bool) {
+        self.failures = if success { 0 } else { self.failures.saturating_add(1) };
+    }
+
+    /// The interval after `every`, grown by the failures so far. Without
+    /// `--every`, it is a second after the first failure and none after a
+    /// success.
+    pub fn interval(&self, every: Option<Duration>) -> Duration {
+        let (every, failures) = match every {
+            Some(every) => (every, self.failures),
+            None if self.failures == 0 => return Duration::from_secs(0),
+            None => (DEFAULT_BACKOFF_BASE, self.failures - 1),
+        };
+        if every.is_zero() {
+            return every;
+        }
+        let failures = failures.min(i32::MAX as u32) as i32;
+        let grown = every.as_secs_f64() * self.factor.powi(failures);
+        Duration::try_from_secs_f64(grown).map_or(self.max, |grown| grown.min(self.max))
+    }
+}
+
+/// `--every-aligned`: the time from `now` to the next multiple of `every`
+/// since the Unix epoch, ex. the next full minute.
+pub fn until_aligned(every: Duration, now: SystemTime) -> Duration {
+    until_multiple(every, now.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+/// `--every-start`: the time to the next tick of `every` since `first`,
+/// skipping the ticks missed already.
+pub fn until_tick(every: Duration, first: Instant) -> Duration {
+    until_multiple(every, first.elapsed())
+}
+
+fn until_multiple(every: Duration, elapsed: Duration) -> Duration {
+    let every = every.as_nanos();
+    match elapsed.as_nanos().checked_rem(every) {
+        Some(0) | None => Duration::from_secs(0),
+        Some(rest) => Duration::from_nanos((every - rest) as u64),
+    }
+}
+
+/// `--max-per`, `--max-lines-per-sec`: a token bucket holding up to
+/// `capacity` iterations or lines, filled at `capacity` per `period`.
+#[derive(Debug)]
+pub struct RateLimit {
+    capacity: f64,
+    period: Duration,
+    tokens: f64,
+    last: Option<Instant>,
+}
+
+/// Parse `N/DURATION`, ex. `10/1m` or `5/s`.
+pub fn parse_rate_limit(input: &str) -> Result<RateLimit, String> {
+    let invalid = || format!("expected N/DURATION, ex. 10/1m, got '{}'", input);
+    let (count, period) = input.split_once('/').ok_or_else(invalid)?;
+    let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+    let period = period.trim();
+    let period = if period.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        parse_duration(&format!("1{}", period))
+    } else {
+        parse_duration(period)
+    }.map_err(|_| invalid())?;
+    if count == 0 || period == Duration::from_secs(0) {
+        return Err(format!("the rate of '{}' must not be zero", input));
+    }
+    Ok(RateLimit::new(count, period))
+}
+
+impl RateLimit {
+    pub fn new(count: u32, period: Duration) -> RateLimit {
+        RateLimit { capacity: count as f64, period, tokens: count as f64, last: None }
+    }
+
+    fn rate(&self) -> f64 {
+        self.capacity / self.period.as_secs_f64()
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            self.tokens = (self.tokens + now.duration_since(last).as_secs_f64() * self.rate()).min(self.capacity);
+        }
+        self.last = Some(now);
+    }
+
+    /// Take a token for an iteration, returning how long to wait for it.
+    pub fn take(&mut self) -> Duration {
+        self.refill();
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate())
+        }
+    }
+
+    /// Take a token if there is one, without waiting.
+    pub fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// `--jitter`: how far a wait may randomly differ from the interval.
+#[derive(Debug, Clone, Copy)]
+pub enum Jitter {
+    Duration(Duration),
+    /// A share of the interval.
+    Percent(f64),
+}
+
+/// Parse a duration, ex. `5s`, or a percentage of the interval, ex. `10%`.
+pub fn parse_jitter(input: &str) -> Result<Jitter, String> {
+    match input.trim().strip_suffix('%') {
+        Some(percent) => match percent.trim().parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(Jitter::Percent(percent)),
+            _ => Err(format!("expected a percentage from 0% to 100%, got '{}'", input)),
+        },
+        None => parse_duration(input.trim()).map(Jitter::Duration).map_err(|e| e.to_string()),
+    }
+}
+
+impl Jitter {
+    /// `interval` moved by a random amount of up to the jitter either way.
+    pub fn apply(&self, interval: Duration, rng: &mut Rng) -> Duration {
+        let jitter = match *self {
+            Jitter::Duration(jitter) => jitter.as_secs_f64(),
+            Jitter::Percent(percent) => interval.as_secs_f64() * percent / 100.0,
+        };
+        let offset = (rng.unit() * 2.0 - 1.0) * jitter;
+        Duration::from_secs_f64((interval.as_secs_f64() + offset).max(0.0))
+    }
+}
@@ -0,0 +1,227 @@
+//! Items streamed into the loop while it runs.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use subprocess::{Exec, Popen, Redirection};
+
+use signal;
+
+/// How often a blocked queue checks whether the loop was interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// `--on-full`: what to do with new items while the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnFull {
+    DropOldest,
+    DropNewest,
+    Block,
+}
+
+pub fn parse_on_full(input: &str) -> Result<OnFull, String> {
+    match input {
+        "drop-oldest" => Ok(OnFull::DropOldest),
+        "drop-newest" => Ok(OnFull::DropNewest),
+        "block" => Ok(OnFull::Block),
+        _ => Err(format!("expected drop-oldest, drop-newest or block, got '{}'", input)),
+    }
+}
+
+/// The variable exporting a field of an item named `name`, ex.
+/// `ITEM_first_name` for `first name`.
+pub fn field_variable(name: &str) -> String {
+    let name: String = name.trim().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("ITEM_{}", name)
+}
+
+/// Settles a consumed item once its iteration finished, ex. by
+/// acknowledging it to a message broker.
+pub trait Receipt: Send {
+    fn settle(self: Box<Self>, success: bool);
+}
+
+pub struct Item {
+    pub value: String,
+    pub receipt: Option<Box<dyn Receipt>>,
+}
+
+impl Item {
+    pub fn new(value: String) -> Item {
+        Item { value, receipt: None }
+    }
+
+    /// A `--batch` of items as one: their values joined by spaces, and
+    /// their receipts settled together.
+    pub fn batch(items: Vec<Item>) -> Item {
+        let value = items.iter().map(|item| item.value.as_str()).collect::<Vec<_>>().join(" ");
+        let receipts: Vec<_> = items.into_iter().filter_map(|item| item.receipt).collect();
+        let receipt: Option<Box<dyn Receipt>> = if receipts.is_empty() { None } else { Some(Box::new(Batch(receipts))) };
+        Item { value, receipt }
+    }
+}
+
+struct Batch(Vec<Box<dyn Receipt>>);
+
+impl Receipt for Batch {
+    fn settle(self: Box<Self>, success: bool) {
+        for receipt in self.0 {
+            receipt.settle(success);
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    items: VecDeque<Item>,
+    closed: bool,
+    dropped: usize,
+}
+
+/// A queue of items between their producers and the loop, bounded by
+/// `--queue-max`.
+pub struct Queue {
+    state: Mutex<State>,
+    changed: Condvar,
+    max: Option<usize>,
+    on_full: OnFull,
+}
+
+impl Queue {
+    pub fn new(max: Option<usize>, on_full: OnFull) -> Arc<Queue> {
+        Arc::new(Queue { state: Mutex::new(State::default()), changed: Condvar::new(), max, on_full })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add an item, applying the `--on-full` policy if the queue is full.
+    pub fn push(&self, item: Item) {
+        let mut state = self.lock();
+        if let Some(max) = self.max {
+            while state.items.len() >= max.max(1) {
+                match self.on_full {
+                    OnFull::DropOldest => {
+                        state.items.pop_front();
+                        state.dropped += 1;
+                    }
+                    OnFull::DropNewest => {
+                        state.dropped += 1;
+                        return;
+                    }
+                    OnFull::Block => {
+                        if signal::interrupted() {
+                            return;
+                        }
+                        state = self.changed.wait_timeout(state, POLL_INTERVAL)
+                            .unwrap_or_else(|e| e.into_inner()).0;
+                    }
+                }
+            }
+        }
+        state.items.push_back(item);
+        self.changed.notify_all();
+    }
+
+    /// Mark the end of the stream.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.changed.notify_all();
+    }
+
+    /// Wait for the next item. Returns `None` once the queue is closed and
+    /// drained, or the loop was interrupted.
+    pub fn pop(&self) -> Option<Item> {
+        let mut state = self.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if state.closed || signal::interrupted() {
+                return None;
+            }
+            state = self.changed.wait_timeout(state, POLL_INTERVAL).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Wait for the next `n` items, fewer only at the end of the stream.
+    pub fn pop_many(&self, n: usize) -> Vec<Item> {
+        let mut items = Vec::new();
+        while items.len() < n {
+            match self.pop() {
+                Some(item) => items.push(item),
+                None => break,
+            }
+        }
+        items
+    }
+
+    /// Number of items dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.lock().dropped
+    }
+}
+
+/// The items of `reader`: its lines, or with `-0`, its NUL terminated
+/// strings.
+pub fn split<R: BufRead>(reader: R, null: bool) -> impl Iterator<Item = String> {
+    let delimiter = if null { b'\0' } else { b'\n' };
+    reader.split(delimiter)
+        .map_while(Result::ok)
+        .map(move |mut item| {
+            if !null && item.last() == Some(&b'\r') {
+                item.pop();
+            }
+            String::from_utf8_lossy(&item).into_owned()
+        })
+}
+
+/// Stream the items of `reader` into `queue` from a thread, closing it at
+/// the end.
+pub fn stream_lines<R: BufRead + Send + 'static>(reader: R, null: bool, queue: Arc<Queue>) {
+    thread::spawn(move || {
+        for item in split(reader, null) {
+            queue.push(Item::new(item));
+        }
+        queue.close();
+    });
+}
+
+/// Stream the output lines of `command` into `queue` from a thread. With
+/// `restart`, the command runs again once it finished, until a run has no
+/// output.
+pub fn generate(command: &str, restart: bool, null: bool, queue: Arc<Queue>) -> io::Result<()> {
+    let spawn = |command: &str| -> io::Result<Popen> {
+        Exec::shell(command).stdout(Redirection::Pipe).popen().map_err(io::Error::other)
+    };
+    let mut generator = spawn(command)?;
+    let command = command.to_owned();
+    thread::spawn(move || {
+        loop {
+            let mut produced = false;
+            if let Some(stdout) = generator.stdout.take() {
+                for item in split(BufReader::new(stdout), null) {
+                    queue.push(Item::new(item));
+                    produced = true;
+                }
+            }
+            let _ = generator.wait();
+            if !restart || !produced || signal::interrupted() {
+                break;
+            }
+            generator = match spawn(&command) {
+                Ok(generator) => generator,
+                Err(e) => {
+                    eprintln!("Failed to restart '{}': {}", command, e);
+                    break;
+                }
+            };
+        }
+        queue.close();
+    });
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! `--junit`: the iterations as test cases of a JUnit XML report, for CI
+//! systems to ingest.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use humantime::format_rfc3339_seconds;
+
+#[derive(Debug)]
+pub struct Junit {
+    path: PathBuf,
+    suite: String,
+    started: SystemTime,
+    start: Instant,
+    cases: Vec<Case>,
+}
+
+#[derive(Debug)]
+struct Case {
+    name: String,
+    duration: Duration,
+    /// The reason the iteration failed, and its output
+    failure: Option<(String, String)>,
+}
+
+/// Escape text for XML attributes and elements.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' | '\t' | '\r' => escaped.push(c),
+            // Not allowed in XML 1.0
+            c if (c as u32) < 0x20 => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Junit {
+    /// A report of the test suite named after the command.
+    pub fn new(path: PathBuf, suite: &str) -> Junit {
+        Junit { path, suite: suite.to_owned(), started: SystemTime::now(), start: Instant::now(), cases: Vec::new() }
+    }
+
+    /// Add an iteration, with the reason it failed if it did.
+    pub fn record(&mut self, name: String, duration: Duration, failure: Option<String>, output: &str) {
+        self.cases.push(Case { name, duration, failure: failure.map(|message| (message, output.to_owned())) });
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        let failures = self.cases.iter().filter(|case| case.failure.is_some()).count();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!("<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{:.3}\" timestamp=\"{}\">\n",
+                              escape(&self.suite), self.cases.len(), failures, self.start.elapsed().as_secs_f64(),
+                              format_rfc3339_seconds(self.started)));
+        for case in &self.cases {
+            let opening = format!("  <testcase name=\"{}\" classname=\"loop\" time=\"{:.3}\"",
+                                  escape(&case.name), case.duration.as_secs_f64());
+            match case.failure {
+                Some((ref message, ref output)) => {
+                    xml.push_str(&format!("{}>\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                                          opening, escape(message), escape(output)));
+                }
+                None => xml.push_str(&format!("{}/>\n", opening)),
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        fs::write(&self.path, xml)
+    }
+}
@@ -0,0 +1,113 @@
+//! Advisory file locks shared between cooperating loop processes.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use signal;
+
+/// How often a held lock is tried again while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long `--take-over` waits for the other loop to exit.
+const TAKE_OVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An exclusive `flock(2)` lock, released when dropped.
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Lock `path`, creating it if necessary. Unless `wait` is set, returns
+    /// `None` instead of blocking when another process holds the lock.
+    /// Waiting fails with `Interrupted` once the loop is interrupted.
+    pub fn acquire(path: &Path, wait: bool) -> io::Result<Option<FileLock>> {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+        // A blocking flock(2) would be restarted after SIGINT, so a held
+        // lock is polled instead.
+        while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::WouldBlock {
+                return Err(error);
+            }
+            if !wait {
+                return Ok(None);
+            }
+            if signal::interrupted() {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            signal::sleep(POLL_INTERVAL);
+        }
+        Ok(Some(FileLock { file }))
+    }
+
+    /// Record the current process as the holder of the lock.
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        write!(self.file, "{}", process::id())
+    }
+}
+
+/// The lock file guarding `--single-instance` for `command`, named after a
+/// hash of the command and the current user.
+pub fn instance_lock_path(command: &str) -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(env::temp_dir);
+    let uid = unsafe { libc::getuid() };
+    dir.join(format!("loop-{}-{:016x}.lock", uid, fnv1a(command.as_bytes())))
+}
+
+/// Take the instance lock for `command`. If another loop holds it, either
+/// give up returning the holder's pid, or with `take_over` terminate that
+/// loop and wait for the lock, failing with `TimedOut` if it doesn't exit
+/// in time or with `Interrupted` once the loop is interrupted.
+pub fn single_instance(command: &str, take_over: bool) -> io::Result<Result<FileLock, String>> {
+    let path = instance_lock_path(command);
+    let mut lock = match FileLock::acquire(&path, false)? {
+        Some(lock) => lock,
+        None => {
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            if !take_over {
+                return Ok(Err(holder));
+            }
+            if let Ok(pid) = holder.trim().parse::<libc::pid_t>() {
+                unsafe { libc::kill(pid, libc::SIGTERM) };
+            }
+            let start = Instant::now();
+            loop {
+                if let Some(lock) = FileLock::acquire(&path, false)? {
+                    break lock;
+                }
+                if signal::interrupted() {
+                    return Err(io::Error::from(io::ErrorKind::Interrupted));
+                }
+                if start.elapsed() > TAKE_OVER_TIMEOUT {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, format!(
+                        "the loop holding the lock (pid {}) did not exit within {}s",
+                        holder.trim(), TAKE_OVER_TIMEOUT.as_secs())));
+                }
+                signal::sleep(POLL_INTERVAL);
+            }
+        }
+    };
+    lock.write_pid()?;
+    Ok(Ok(lock))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        unsafe {
+            libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+        }
+    }
+}
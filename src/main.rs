@@ -4,69 +4,492 @@ extern crate atty;
 extern crate regex;
 extern crate subprocess;
 extern crate tempfile;
+extern crate libc;
 
+mod clipboard;
+mod collect;
+mod config;
+mod cron;
+mod csv;
+mod dbus;
+mod error;
+mod git;
+mod growth;
+mod guard;
+mod interval;
+mod items;
+mod junit;
+mod json;
+mod lock;
+mod mqtt;
+mod nats;
+mod ping;
+mod procfs;
+mod random;
+mod redis;
+mod report;
+mod resume;
+mod sandbox;
+mod sched;
+mod sha256;
+mod signal;
+mod stats;
+mod tee;
+mod template;
+mod time;
+mod watchdog;
+
+use std::collections::{HashSet, VecDeque};
 use std::env;
+use std::ffi::OsString;
 use std::f64;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::io::prelude::*;
-use std::io::{self, BufRead, SeekFrom};
+use std::io::{self, SeekFrom};
+use std::net::{IpAddr, ToSocketAddrs};
 use std::process;
+use std::sync::atomic::Ordering;
 use std::thread;
-use std::time::{Duration, Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use humantime::{parse_duration, parse_rfc3339_weak};
-use regex::Regex;
-use subprocess::{Exec, ExitStatus, Redirection};
+use humantime::{format_duration, parse_duration};
+use regex::{Regex, RegexBuilder};
+use subprocess::{Exec, ExitStatus, NullFile, Popen, Redirection};
 use structopt::StructOpt;
+use structopt::clap::ArgMatches;
+
+use collect::{collect, path_component};
+use error::LoopError;
+use items::{parse_on_full, Item, OnFull, Queue};
+use guard::{parse_battery, parse_cpu_usage, parse_free_space, parse_percent, parse_temperature, CpuUsage, FreeSpace};
+use json::Json;
+use lock::FileLock;
+use resume::{parse_after_resume, AfterResume};
+use sandbox::Sandbox;
+use stats::{parse_aggregate, parse_failure_rate, parse_threshold, parse_trend, Aggregate, AggregateLimit, FailureRate,
+            Hysteresis, Statistic, Threshold, Trend};
+use tee::Tee;
+use template::{parse_env_template, parse_env_template_file, Context, EnvTemplate, Template};
+use time::{parse_http_date, parse_until_time, UntilTime};
+use watchdog::Limits;
 
 static UNKONWN_EXIT_CODE: u32 = 99;
 
-// same exit code as use of `timeout` shell command
-static TIMEOUT_EXIT_CODE: i32 = 124;
+/// Characters of the `--plot` sparkline at most.
+const PLOT_WIDTH: usize = 60;
 
 fn main() {
 
-    // Load the CLI arguments
-    let opt = Opt::from_args();
-    let count_precision = Opt::clap()
-        .get_matches()
+    // Load the CLI arguments, completed by LOOP_* variables, then by
+    // --config and --config-stdin
+    let args: Vec<OsString> = env::args_os().collect();
+    let cli = parse_args(&args);
+    let cli_opt = Opt::from_clap(&cli);
+    let loaded = config::load(None, cli_opt.config_stdin).and_then(|stdin_settings| {
+        let args = load_options(&args, &cli, cli_opt.config.as_deref(), &stdin_settings)?;
+        Ok((stdin_settings, args))
+    });
+    let (stdin_settings, matches) = match loaded {
+        Ok((stdin_settings, args)) => (stdin_settings, parse_args(&args)),
+        Err(e) => LoopError::Usage(format!("Invalid config: {}", e)).exit(cli_opt.errors == "json"),
+    };
+    let mut opt = Opt::from_clap(&matches);
+    let json_errors = opt.errors == "json";
+    let count_precision = matches
         .value_of("count_by")
         .map(precision_of)
         .unwrap_or(0);
 
+    if opt.print_config {
+        let settings = config::resolve(OPTIONS, &matches);
+        println!("# {}", config::to_command_line(OPTIONS, &settings));
+        print!("{}", config::to_toml(&settings));
+        return;
+    }
+
     let mut exit_status = 0;
+    let mut error = None;
 
     // Time
     let program_start = Instant::now();
 
     // Number of iterations
-    let mut items = if let Some(items) = opt.ffor { items.clone() } else { vec![] };
-
-    // Get any lines from stdin
-    if opt.stdin || atty::isnt(atty::Stream::Stdin) {
-        let stdin = io::stdin();
-        for line in stdin.lock().lines() {
-            items.push(line.unwrap().to_owned())
+    // --for, --for-file, --zip
+    // Several lists are combined, into all combinations or with --zip
+    // positionally, their values exported one by one. The files are read
+    // up front then, following the --for lists.
+    let mut items = Vec::new();
+    let mut item_variables: Vec<Vec<(String, String)>> = Vec::new();
+    let mut lists = opt.ffor.clone();
+    if lists.len() + opt.for_file.len() > 1 || opt.shuffle.is_some() || opt.cycle {
+        for path in &opt.for_file {
+            let file = fs::File::open(path)
+                .unwrap_or_else(|e| LoopError::Io(format!("Failed to open {}: {}", path.display(), e)).exit(json_errors));
+            lists.push(items::split(io::BufReader::new(file), opt.null).collect());
+        }
+    }
+    if lists.len() == 1 {
+        items = lists.remove(0);
+    } else if lists.len() > 1 {
+        let combinations = if opt.zip {
+            let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+            (0..len).map(|i| lists.iter().map(|list| list[i].clone()).collect()).collect()
+        } else {
+            let mut combinations = vec![Vec::new()];
+            for list in &lists {
+                combinations = combinations.iter()
+                    .flat_map(|combination: &Vec<String>| list.iter().map(move |value| {
+                        let mut combination = combination.clone();
+                        combination.push(value.clone());
+                        combination
+                    }))
+                    .collect();
+            }
+            combinations
+        };
+        for combination in combinations {
+            items.push(combination.join(","));
+            item_variables.push(combination.into_iter().enumerate()
+                .map(|(i, value)| (format!("ITEM{}", i + 1), value))
+                .collect());
         }
     }
 
     let joined_input = &opt.input.join(" ");
-    if joined_input == "" {
+    if joined_input.is_empty() {
         println!("No command supplied, exiting.");
         return;
     }
 
+    // --replace
+    // A single command string is split into its words like a shell does,
+    // and the item is substituted into them one by one.
+    let argv: Vec<String> = match (&opt.replace, opt.input.as_slice()) {
+        (None, _) => Vec::new(),
+        (Some(_), [command]) => split_words(command)
+            .unwrap_or_else(|e| LoopError::Usage(format!("Invalid command for --replace: {}", e)).exit(json_errors)),
+        (Some(_), input) => input.to_vec(),
+    };
+    if opt.replace.is_some() && argv.is_empty() {
+        LoopError::Usage("--replace needs a command to run".to_owned()).exit(json_errors);
+    }
+
+    // --single-instance
+    let _instance_lock = if opt.single_instance || opt.take_over {
+        match lock::single_instance(joined_input, opt.take_over) {
+            Ok(Ok(lock)) => Some(lock),
+            Ok(Err(holder)) => LoopError::AlreadyRunning(holder.trim().to_owned()).exit(json_errors),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => LoopError::Interrupted.exit(json_errors),
+            Err(e) => LoopError::Io(format!("Failed to lock: {}", e)).exit(json_errors),
+        }
+    } else {
+        None
+    };
+
+    // --consume
+    let mut queue = None;
+    if let Some(ref source) = opt.consume {
+        let stream = Queue::new(opt.queue_max, opt.on_full.unwrap_or(OnFull::Block));
+        if let Err(e) = nats::consume(source, stream.clone(), opt.ack_on_success) {
+            LoopError::Io(format!("Failed to consume from {}: {}", source.address, e)).exit(json_errors);
+        }
+        queue = Some(stream);
+    }
+
+    // --for-redis
+    // Items stay in the list until the loop gets to them.
+    if let Some(ref source) = opt.for_redis {
+        let stream = Queue::new(opt.queue_max.or(Some(1)), opt.on_full.unwrap_or(OnFull::Block));
+        let address = opt.redis_address.as_deref().unwrap_or(redis::DEFAULT_ADDRESS);
+        if let Err(e) = redis::consume(address, source, opt.dead_letter.clone(), stream.clone()) {
+            LoopError::Io(format!("Failed to connect to Redis at {}: {}", address, e)).exit(json_errors);
+        }
+        queue = Some(stream);
+    }
+
+    // --mqtt-trigger, --until-mqtt
+    let mut mqtt_matched = None;
+    if opt.mqtt_trigger.is_some() || !opt.until_mqtt.is_empty() {
+        let (queue_max, on_full) = (opt.queue_max, opt.on_full.unwrap_or(OnFull::Block));
+        let trigger = opt.mqtt_trigger.clone().map(|topic| (topic, Queue::new(queue_max, on_full)));
+        if let Some((_, ref stream)) = trigger {
+            queue = Some(stream.clone());
+        }
+        let until = opt.until_mqtt.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+        let address = opt.mqtt_broker.as_deref().unwrap_or(mqtt::DEFAULT_ADDRESS);
+        match mqtt::subscribe(address, trigger, until) {
+            Ok(matched) => mqtt_matched = Some(matched),
+            Err(e) => LoopError::Io(format!("Failed to connect to MQTT broker at {}: {}", address, e)).exit(json_errors),
+        }
+    }
+
+    // --on-dbus
+    if let Some(ref rule) = opt.on_dbus {
+        let stream = Queue::new(opt.queue_max, opt.on_full.unwrap_or(OnFull::Block));
+        if let Err(e) = dbus::watch(rule, opt.dbus_session, stream.clone()) {
+            LoopError::Io(format!("Failed to watch D-Bus: {}", e)).exit(json_errors);
+        }
+        queue = Some(stream);
+    }
+
+    // --for-csv, --csv-header
+    // Rows are the items, their columns are exported one by one.
+    if let Some(ref path) = opt.for_csv {
+        let mut rows = fs::read_to_string(path).map_err(|e| e.to_string())
+            .and_then(|contents| csv::parse(&contents))
+            .unwrap_or_else(|e| LoopError::Io(format!("Failed to read {}: {}", path.display(), e)).exit(json_errors));
+        let header: Vec<String> = if opt.csv_header && !rows.is_empty() {
+            rows.remove(0).iter().map(|name| items::field_variable(name)).collect()
+        } else {
+            Vec::new()
+        };
+        let width = rows.iter().map(Vec::len).chain(Some(header.len())).max().unwrap_or(0);
+        for row in rows {
+            let mut variables = Vec::new();
+            for column in 0..width {
+                let value = row.get(column).cloned().unwrap_or_default();
+                if let Some(name) = header.get(column) {
+                    variables.push((name.clone(), value.clone()));
+                }
+                variables.push((format!("ITEM{}", column + 1), value));
+            }
+            items.push(row.join(","));
+            item_variables.push(variables);
+        }
+    }
+
+    // --for-clipboard
+    if opt.for_clipboard {
+        let text = clipboard::paste()
+            .unwrap_or_else(|e| LoopError::Io(format!("Failed to read the clipboard: {}", e)).exit(json_errors));
+        items.extend(items::split(text.as_bytes(), opt.null).filter(|item| !item.is_empty()));
+    }
+
+    // --for-jsonl, --field
+    // Objects are the items, the selected fields are exported.
+    if let Some(ref path) = opt.for_jsonl {
+        let contents = fs::read_to_string(path)
+            .unwrap_or_else(|e| LoopError::Io(format!("Failed to read {}: {}", path.display(), e)).exit(json_errors));
+        for (number, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let object = Json::parse(line).unwrap_or_else(|e| {
+                LoopError::Io(format!("Invalid JSON on line {} of {}: {}", number + 1, path.display(), e)).exit(json_errors)
+            });
+            let variables = opt.field.iter()
+                .map(|field| (items::field_variable(field), object.pointer(field).map(Json::to_text).unwrap_or_default()))
+                .collect();
+            items.push(line.to_owned());
+            item_variables.push(variables);
+        }
+    }
+
+    // --for-cmd, --restart-for-cmd
+    // A restarting generator is held back until the loop needs its items.
+    if let Some(ref command) = opt.for_cmd {
+        let queue_max = if opt.restart_for_cmd { opt.queue_max.or(Some(1)) } else { opt.queue_max };
+        let stream = Queue::new(queue_max, opt.on_full.unwrap_or(OnFull::Block));
+        if let Err(e) = items::generate(command, opt.restart_for_cmd, opt.null, stream.clone()) {
+            LoopError::SpawnFailed(e.to_string()).exit(json_errors);
+        }
+        queue = Some(stream);
+    }
+
+    // --for-file
+    // Standard input is left to the command.
+    if opt.for_file.len() == 1 && opt.ffor.is_empty() && opt.shuffle.is_none() && !opt.cycle {
+        let path = &opt.for_file[0];
+        let file = fs::File::open(path)
+            .unwrap_or_else(|e| LoopError::Io(format!("Failed to open {}: {}", path.display(), e)).exit(json_errors));
+        let stream = Queue::new(opt.queue_max, opt.on_full.unwrap_or(OnFull::Block));
+        items::stream_lines(io::BufReader::new(file), opt.null, stream.clone());
+        queue = Some(stream);
+    }
+
+    // Get any lines from stdin
+    // With --queue-max they are streamed while the loop runs.
+    if queue.is_none() && opt.for_csv.is_none() && opt.for_jsonl.is_none() && !opt.for_clipboard && opt.bisect.is_none() && opt.bisect_git.is_none()
+        && (opt.stdin || atty::isnt(atty::Stream::Stdin)) {
+        if opt.queue_max.is_some() {
+            let stream = Queue::new(opt.queue_max, opt.on_full.unwrap_or(OnFull::Block));
+            items::stream_lines(io::BufReader::new(io::stdin()), opt.null, stream.clone());
+            queue = Some(stream);
+        } else {
+            let stdin = io::stdin();
+            items.extend(items::split(stdin.lock(), opt.null));
+        }
+    }
+
+    // --shuffle
+    // A seed is picked unless given, and reported to repeat the order.
+    if let Some(seed) = opt.shuffle {
+        let seed = seed.unwrap_or_else(|| {
+            let seed = random::Rng::random_seed();
+            eprintln!("Shuffled with seed {}", seed);
+            seed
+        });
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        random::Rng::new(seed).shuffle(&mut order);
+        items = order.iter().map(|&i| items[i].clone()).collect();
+        if !item_variables.is_empty() {
+            item_variables = order.iter().map(|&i| item_variables[i].clone()).collect();
+        }
+    }
+
+    // --batch
+    if let Some(n) = opt.batch {
+        item_variables = items.chunks(n).map(|batch| batch_variables(batch, n)).collect();
+        items = items.chunks(n).map(|batch| batch.join(" ")).collect();
+    }
+
     // Counters and State
     let num = if let Some(num) = opt.num {
         num
+    } else if opt.cycle || opt.bisect.is_some() || opt.bisect_git.is_some() {
+        f64::INFINITY
+    } else if let Some(ref range) = opt.for_range {
+        range.len() as f64
+    } else if queue.is_some() {
+        f64::INFINITY
     } else if !items.is_empty() {
         items.len() as f64
     } else {
         f64::INFINITY
     };
     let mut has_matched = false;
-    let mut tmpfile = tempfile::tempfile().unwrap();
-    let mut summary = Summary { successes: 0, failures: Vec::new() };
+    let mut confirmations = 0;
+    // --until-match-count
+    let mut occurrences = 0;
+    let mut last_output: Option<fs::File> = None;
+    let mut summary = Summary {
+        successes: 0,
+        failures: Vec::new(),
+        failed_seeds: opt.seed.map(|_| Vec::new()),
+        slow_runs: opt.warn_slower_than.map(|_| 0),
+        dropped_items: None,
+        extracted: opt.extract_kv.then(Vec::new),
+        aggregate: opt.aggregate.take(),
+        durations: opt.plot.then(Vec::new),
+    };
     let mut previous_stdout = None;
+    let stdin_broadcast = opt.stdin_broadcast.as_ref()
+        .map(|payload| read_payload(payload).unwrap_or_else(|e| e.exit(json_errors)));
+    let limits = Limits {
+        max_memory: opt.kill_if_mem,
+        max_cpu_time: opt.cpu_time_limit,
+        timeout: opt.timeout,
+        max_output: opt.max_output,
+        kill_after: opt.kill_after,
+    };
+    let mut until_error = until_error_of(&opt.until_error)
+        .unwrap_or_else(|e| LoopError::ConditionParse(e).exit(json_errors));
+    // --until-contains, --until-match, --while-contains, --while-match
+    let mut patterns = Patterns::of(&opt)
+        .unwrap_or_else(|e| LoopError::Usage(e).exit(json_errors));
+    // --until-mean-gt, --until-mean-lt, --until-p50-gt, --until-p95-gt,
+    // --until-p99-gt
+    let aggregate_limits: Vec<AggregateLimit> = [
+        ("mean", Statistic::Mean, true, opt.until_mean_gt),
+        ("mean", Statistic::Mean, false, opt.until_mean_lt),
+        ("p50", Statistic::Percentile(50.0), true, opt.until_p50_gt),
+        ("p95", Statistic::Percentile(95.0), true, opt.until_p95_gt),
+        ("p99", Statistic::Percentile(99.0), true, opt.until_p99_gt),
+    ].iter()
+        .filter_map(|&(name, statistic, above, value)| value.map(|value| AggregateLimit { name, statistic, above, value }))
+        .collect();
+    // --until-file-size, --until-file-lines
+    let mut file_conditions: Vec<growth::FileCondition> = opt.until_file_size.chunks(3)
+        .map(|values| growth::parse(growth::Metric::Bytes, values))
+        .chain(opt.until_file_lines.chunks(3).map(|values| growth::parse(growth::Metric::Lines, values)))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| LoopError::ConditionParse(e).exit(json_errors));
+    let merge_dir = opt.merge.as_ref().map(|_| {
+        tempfile::Builder::new().prefix("loop-merge-").tempdir()
+            .unwrap_or_else(|e| LoopError::Io(format!("Failed to create merge directory: {}", e)).exit(json_errors))
+    });
+    // --env-template-file
+    // Variables given with --env-template are exported last, so they win.
+    if let Some(mut env_templates) = opt.env_template_file.take() {
+        env_templates.append(&mut opt.env_template);
+        opt.env_template = env_templates;
+    }
+    let exit = opt.exit.take();
+    let mut hysteresis = opt.enter.take().map(|enter| Hysteresis::new(enter, exit));
+    let mut checkpoints = parse_checkpoints(&opt.checkpoint_every)
+        .unwrap_or_else(|e| LoopError::Usage(e).exit(json_errors));
+
+    // --nice, --pin-cpus
+    if let Some(niceness) = opt.nice {
+        if let Err(e) = sched::renice(niceness) {
+            LoopError::Io(format!("Failed to change niceness: {}", e)).exit(json_errors);
+        }
+    }
+    if opt.pin_cpus && !sched::PINNING_SUPPORTED {
+        LoopError::Usage("--pin-cpus is only supported on Linux".to_owned()).exit(json_errors);
+    }
+    // --kill-if-mem
+    if opt.kill_if_mem.is_some() && !procfs::SUPPORTED {
+        LoopError::Usage("--kill-if-mem is only supported on Linux".to_owned()).exit(json_errors);
+    }
+    // --cpu-time-limit
+    if opt.cpu_time_limit.is_some() && !procfs::SUPPORTED {
+        LoopError::Usage("--cpu-time-limit is only supported on Linux".to_owned()).exit(json_errors);
+    }
+    // --until-process, --while-process
+    if (opt.until_process.is_some() || opt.while_process.is_some()) && !procfs::SUPPORTED {
+        LoopError::Usage("--until-process and --while-process are only supported on Linux".to_owned()).exit(json_errors);
+    }
+    let cpus = if opt.pin_cpus {
+        match sched::allowed_cpus() {
+            Ok(ref cpus) if cpus.is_empty() => LoopError::Io("No CPU to pin to".to_owned()).exit(json_errors),
+            Ok(cpus) => cpus,
+            Err(e) => LoopError::Io(format!("Failed to get CPU affinity: {}", e)).exit(json_errors),
+        }
+    } else {
+        Vec::new()
+    };
+
+    signal::install();
+    if cli_opt.config.is_some() {
+        signal::install_hangup();
+    }
+
+    // Reload --config on SIGHUP
+    // The settings in effect, to tell which changes aren't applied.
+    let mut in_effect = config::resolve(OPTIONS, &matches);
+
+    // --backoff
+    // The interval grows from --every only if it was given.
+    let mut every_given = matches.occurrences_of("every") > 0;
+
+    // --until-time, --utc
+    let mut until_time = opt.until_time.as_ref().map(|until_time| until_time.resolve(opt.utc, SystemTime::now()));
+    // The value given, resolved again on SIGHUP only when it changed.
+    let mut until_time_setting = (matches.value_of("until_time").map(str::to_owned), opt.utc);
+    if until_time.is_some_and(|until_time| until_time <= SystemTime::now()) {
+        warn("The --until-time has passed already, not running the command");
+    }
+
+    // --collect
+    // The working directory and the canonical --collect-dir, which isn't
+    // collected from itself.
+    let collect_dirs = match (opt.collect.is_empty(), &opt.collect_dir) {
+        (false, Some(collect_dir)) => {
+            let cwd = env::current_dir()
+                .unwrap_or_else(|e| LoopError::Io(format!("Failed to get the current directory: {}", e)).exit(json_errors));
+            let root = fs::create_dir_all(collect_dir).and_then(|_| collect_dir.canonicalize())
+                .unwrap_or_else(|e| LoopError::Io(format!("Failed to create {}: {}", collect_dir.display(), e)).exit(json_errors));
+            Some((cwd, root))
+        }
+        _ => None,
+    };
+
+    // --sync-start
+    if let Some(ref sync_start) = opt.sync_start {
+        wait_for_sync_start(sync_start, opt.utc);
+        if signal::interrupted() {
+            LoopError::Interrupted.exit(json_errors);
+        }
+    }
 
     let counter = Counter {
             start: opt.offset - opt.count_by,
@@ -74,155 +497,1093 @@ fn main() {
             end: num,
             step_by: opt.count_by
     };
-    for (count, actual_count) in counter.enumerate() {
+    // --jobs
+    // Up to N iterations run at once. They are finished in the order they
+    // started, so their outputs and conditions are too.
+    let jobs = opt.jobs.unwrap_or(1).max(1);
+    let mut iterations = counter.enumerate();
+    let mut running: VecDeque<Running> = VecDeque::new();
+    let mut starting = true;
+    let mut last_start: Option<Instant> = None;
+    let mut first_start = None;
+    let mut last_mark = None;
+    let mut missed = 0;
+    let mut retry_after = None;
+    // --stop-on-first-success-per-item, --budget
+    // The items explored are told apart by their first value.
+    let for_range = &opt.for_range;
+    let sweep_key = |index: usize| -> Option<String> {
+        item_variables.get(index)
+            .and_then(|variables| variables.iter().find(|(name, _)| name == "ITEM1"))
+            .map(|(_, value)| value.clone())
+            .or_else(|| for_range.as_ref().and_then(|range| range.get(index)))
+            .or_else(|| items.get(index).cloned())
+    };
+    let mut sweep = if opt.stop_on_first_success_per_item || opt.budget.is_some() {
+        let len = opt.for_range.as_ref().map_or(items.len(), Range::len);
+        Some(Sweep::new((0..len).filter_map(sweep_key).collect::<HashSet<_>>().len()))
+    } else {
+        None
+    };
+    // --bisect-git
+    let mut git_bisect = opt.bisect_git.as_ref().map(|range| {
+        git::Bisect::start(range)
+            .unwrap_or_else(|e| LoopError::Io(format!("Failed to start git bisect: {}", e)).exit(json_errors))
+    });
+    // --report
+    // Why the loop stopped, unless for a condition, an error or the end of
+    // the iterations.
+    let mut stop_reason = None;
+    let mut report = opt.report.clone().map(|path| {
+        let settings = config::resolve(OPTIONS, &matches);
+        report::Report::new(path, sha256::hex_digest(config::to_command_line(OPTIONS, &settings).as_bytes()))
+    });
+    // --tap
+    let mut tap_tests = 0;
+    // --tag
+    // The tag of an outer loop is not passed on to the commands.
+    if opt.tag.is_none() {
+        env::remove_var("LOOP_TAG");
+    }
+    // --discard-stderr, --stderr-to-stdout
+    // --stderr-to-stdout wins, ex. over a --config file.
+    let discard_stderr = opt.discard_stderr && !opt.stderr_to_stdout;
+    // --junit
+    let mut junit = opt.junit.clone().map(|path| junit::Junit::new(path, joined_input));
+    // --max-lines-per-sec
+    let mut line_limit = opt.max_lines_per_sec.map(|lines| interval::RateLimit::new(lines, Duration::from_secs(1)));
+    // --jitter, --chaos, --reshuffle-each-cycle
+    let mut rng = random::Rng::new(opt.seed.unwrap_or_else(random::Rng::random_seed));
+    // --cycle
+    let mut cycle_order: Vec<usize> = if opt.cycle {
+        (0..opt.for_range.as_ref().map_or(items.len(), Range::len)).collect()
+    } else {
+        Vec::new()
+    };
+    loop {
+        while starting && running.len() < jobs {
+            // Delay until next iteration time
+            // With --after-resume, iterations missed while suspended go
+            // without delay.
+            // --respect-retry-after overrides --every once.
+            // --every-aligned waits for the next multiple of --every on the
+            // clock, also before the first iteration, as --cron does for the
+            // next time it fires.
+            // --every-start keeps to ticks of --every since the first
+            // iteration, skipping those missed by long iterations.
+            let mut every = opt.backoff.as_ref()
+                .map_or(opt.every, |backoff| backoff.interval(Some(opt.every).filter(|_| every_given)));
+            if let Some(jitter) = opt.jitter {
+                every = jitter.apply(every, &mut rng);
+            }
+            // --chaos-wait
+            if let Some(chaos_wait) = opt.chaos_wait {
+                every += Duration::from_secs_f64(rng.unit() * chaos_wait.as_secs_f64());
+            }
+            match (last_start, last_mark, opt.after_resume) {
+                _ if missed > 0 => missed -= 1,
+                (Some(_), _, _) if retry_after.is_some() => signal::sleep(retry_after.take().unwrap_or_default()),
+                _ if opt.cron.is_some() => if let Some(next) = opt.cron.as_ref().and_then(|cron| cron.next(SystemTime::now())) {
+                    signal::sleep(next.duration_since(SystemTime::now()).unwrap_or_default());
+                },
+                (Some(_), _, _) if opt.every_start => if let Some(first_start) = first_start {
+                    signal::sleep(interval::until_tick(every, first_start));
+                },
+                _ if opt.every_aligned => signal::sleep(interval::until_aligned(every, SystemTime::now())),
+                (_, Some(mark), Some(after_resume)) => missed = resume::wait(every, mark, after_resume),
+                (Some(last_start), _, _) => if let Some(time) = every.checked_sub(last_start.elapsed()) {
+                    signal::sleep(time);
+                },
+                _ => {}
+            }
+
+            // Any break from here on stops starting iterations.
+            starting = false;
+            let (count, actual_count) = match iterations.next() {
+                Some(next) => next,
+                None => break,
+            };
 
-        // Time Start
-        let loop_start = Instant::now();
+            // Reload --config on SIGHUP
+            // Counters, summary and the state of rolling conditions are kept.
+            if let (true, Some(path)) = (signal::take_hangup(), cli_opt.config.as_ref()) {
+                let reloaded = load_options(&args, &cli, Some(path), &stdin_settings)
+                    .and_then(|args| Opt::clap().get_matches_from_safe(args).map_err(|e| e.message))
+                    .and_then(|matches| {
+                        let new = Opt::from_clap(&matches);
+                        Ok((until_error_of(&new.until_error)?, parse_checkpoints(&new.checkpoint_every)?, Patterns::of(&new)?,
+                            matches.value_of("until_time").map(str::to_owned), matches.occurrences_of("every") > 0,
+                            config::resolve(OPTIONS, &matches), new))
+                    });
+                match reloaded {
+                    Ok((new_until_error, new_checkpoints, new_patterns, new_until_time, new_every_given, new_settings, new)) => {
+                        for key in config::changed(&in_effect, &new_settings) {
+                            if !RELOADED.contains(&key) {
+                                warn(&format!("Not applying the change of '{}' in {}, which needs a restart", key, path.display()));
+                            }
+                        }
+                        in_effect.retain(|(key, _)| !RELOADED.contains(&key.as_str()));
+                        in_effect.extend(new_settings.into_iter().filter(|(key, _)| RELOADED.contains(&key.as_str())));
+                        opt.every = new.every;
+                        every_given = new_every_given;
+                        opt.for_duration = new.for_duration;
+                        // A relative --until-time, ex. +2h, or a time on the
+                        // clock keeps its deadline unless it was changed.
+                        let new_until_time_setting = (new_until_time, new.utc);
+                        if new_until_time_setting != until_time_setting {
+                            until_time = new.until_time.as_ref().map(|until_time| until_time.resolve(new.utc, SystemTime::now()));
+                            until_time_setting = new_until_time_setting;
+                        }
+                        opt.until_contains = new.until_contains;
+                        opt.until_match = new.until_match;
+                        opt.until_match_count = new.until_match_count;
+                        opt.while_contains = new.while_contains;
+                        opt.while_match = new.while_match;
+                        opt.until_changes = new.until_changes;
+                        opt.until_same = new.until_same;
+                        opt.until_success = new.until_success;
+                        opt.until_fail = new.until_fail;
+                        until_error = new_until_error;
+                        checkpoints = new_checkpoints;
+                        patterns = new_patterns;
+                        opt.heartbeat_file = new.heartbeat_file;
+                        opt.warn_slower_than = new.warn_slower_than;
+                        eprintln!("Reloaded {}", path.display());
+                    }
+                    Err(e) => warn(&format!("Failed to reload {}: {}", path.display(), e)),
+                }
+            }
 
-        // Set counters before execution
-        // THESE ARE FLIPPED AND I CAN'T UNFLIP THEM.
-        env::set_var("ACTUALCOUNT", count.to_string());
-        env::set_var("COUNT", format!("{:.*}", count_precision, actual_count));
+            // Time Start
+            let loop_start = Instant::now();
+            last_start = Some(loop_start);
+            first_start.get_or_insert(loop_start);
+            last_mark = opt.after_resume.map(|_| resume::Mark::now());
 
-        // Set iterated item as environment variable
-        if let Some(item) = items.get(count) {
-            env::set_var("ITEM", item);
-        }
+            // Set counters before execution
+            // THESE ARE FLIPPED AND I CAN'T UNFLIP THEM.
+            env::set_var("ACTUALCOUNT", count.to_string());
+            env::set_var("COUNT", format!("{:.*}", count_precision, actual_count));
+
+            // Set iterated item as environment variable
+            // --cycle starts over from the first item after the last one.
+            // --reshuffle-each-cycle and --rotate change the order of every
+            // pass after the first.
+            let cycled = cycle_order.len();
+            let index = if let Some(pass) = count.checked_div(cycled) {
+                if opt.reshuffle_each_cycle && pass > 0 && count % cycled == 0 {
+                    rng.shuffle(&mut cycle_order);
+                }
+                let rotation = (pass % cycled) * (opt.rotate.unwrap_or(0) % cycled);
+                cycle_order[(count % cycled + rotation) % cycled]
+            } else {
+                count
+            };
+            let streamed = match queue {
+                Some(ref queue) if index >= items.len() => match opt.batch {
+                    // --batch
+                    Some(n) => {
+                        let batch = queue.pop_many(n);
+                        if batch.is_empty() {
+                            break;
+                        }
+                        let values: Vec<String> = batch.iter().map(|item| item.value.clone()).collect();
+                        for (name, value) in batch_variables(&values, n) {
+                            env::set_var(name, value);
+                        }
+                        Some(Item::batch(batch))
+                    }
+                    None => match queue.pop() {
+                        Some(item) => Some(item),
+                        None => break,
+                    },
+                },
+                _ => None,
+            };
+            // --bisect, --bisect-git
+            // The value tested is exported as $COUNT too, the revision
+            // checked out as $ITEM.
+            let ranged = match (&opt.bisect, &git_bisect) {
+                (Some(bisect), _) => match bisect.next() {
+                    Some(value) => {
+                        env::set_var("COUNT", value.to_string());
+                        Some(value.to_string())
+                    }
+                    None => break,
+                },
+                (_, Some(git_bisect)) => match git_bisect.revision() {
+                    Some(revision) => Some(revision.to_owned()),
+                    None => break,
+                },
+                _ => opt.for_range.as_ref().and_then(|range| range.get(index)),
+            };
+            let item = ranged.as_ref().or(items.get(index)).or(streamed.as_ref().map(|item| &item.value));
+            if let Some(item) = item {
+                env::set_var("ITEM", item);
+            }
+            // --for, --for-csv, --for-jsonl, --batch
+            for (name, value) in item_variables.get(index).into_iter().flatten() {
+                env::set_var(name, value);
+            }
+
+            // --budget
+            if let (Some(budget), true) = (opt.budget, sweep.is_some()) {
+                if program_start.elapsed() >= budget {
+                    eprintln!("Budget of {} spent", format_duration(budget));
+                    stop_reason = Some("budget");
+                    break;
+                }
+            }
+            // --stop-on-first-success-per-item
+            // Items which succeeded already are skipped without a wait.
+            let key = sweep.as_ref().and_then(|_| sweep_key(index)).map(|key| (key, item.cloned().unwrap_or_default()));
+            if let (Some(ref sweep), Some((ref key, _))) = (&sweep, &key) {
+                if opt.stop_on_first_success_per_item && sweep.succeeded(key) {
+                    if sweep.all_succeeded() {
+                        break;
+                    }
+                    missed += 1;
+                    starting = true;
+                    continue;
+                }
+            }
+
+            // --seed
+            // Every iteration gets its own, reproducible seed.
+            let seed = opt.seed.map(|seed| seed.wrapping_add(count as u64));
+            if let Some(seed) = seed {
+                env::set_var("LOOP_SEED", seed.to_string());
+            }
+
+            // --chaos, --chaos-delay
+            // The command is told to fail, and how long to stall, at random.
+            if let Some(probability) = opt.chaos {
+                let fail = rng.unit() * 100.0 < probability;
+                env::set_var("CHAOS_FAIL", if fail { "1" } else { "0" });
+                let delay = opt.chaos_delay.map_or(0, |max| rng.below(max.as_millis() as u64 + 1));
+                env::set_var("CHAOS_DELAY_MS", delay.to_string());
+            }
+
+            // --tag, --env-template
+            let mut tag = None;
+            if opt.tag.is_some() || !opt.env_template.is_empty() {
+                let formatted_count = format!("{:.*}", count_precision, actual_count);
+                let mut context = Context {
+                    count: &formatted_count,
+                    actual_count: count,
+                    item: item.map(String::as_str),
+                    tag: None,
+                    now: SystemTime::now(),
+                };
+                tag = opt.tag.as_ref().map(|template| template.render(&context));
+                if let Some(ref tag) = tag {
+                    env::set_var("LOOP_TAG", tag);
+                }
+                context.tag = tag.as_deref();
+                for env_template in &opt.env_template {
+                    env::set_var(&env_template.key, env_template.template.render(&context));
+                }
+            }
 
-        // Finish if we're over our duration
-        if let Some(duration) = opt.for_duration {
-            let since = Instant::now().duration_since(program_start);
-            if since >= duration {
-                if opt.error_duration {
-                    exit_status = TIMEOUT_EXIT_CODE
+            // Finish if we're over our duration
+            if let Some(duration) = opt.for_duration {
+                let since = Instant::now().duration_since(program_start);
+                if since >= duration {
+                    if opt.error_duration {
+                        error = Some(LoopError::Timeout(duration));
+                    }
+                    stop_reason = Some("duration");
+                    break;
+                }
+            }
+
+            // Finish if our time until has passed
+            // A time passed already when the loop starts runs no iteration.
+            if let Some(until_time) = until_time {
+                if SystemTime::now().duration_since(until_time).is_ok() {
+                    stop_reason = Some("until-time");
+                    break;
+                }
+            }
+
+            // --min-free-space
+            if let Some(ref min_free_space) = opt.min_free_space {
+                if opt.on_low_space == "abort" {
+                    if let Some(reason) = min_free_space.check() {
+                        error = Some(LoopError::LowSpace(reason));
+                        break;
+                    }
+                } else {
+                    guard::wait_while(|| min_free_space.check());
                 }
+            }
+
+            // --max-load, --max-cpu
+            if opt.max_load.is_some() || opt.max_cpu.is_some() {
+                let max_load = opt.max_load;
+                let max_cpu = &mut opt.max_cpu;
+                guard::wait_while(|| {
+                    max_load.and_then(guard::check_load)
+                        .or_else(|| max_cpu.as_mut().and_then(CpuUsage::check))
+                });
+            }
+
+            // --only-on-ac, --min-battery
+            if opt.only_on_ac || opt.min_battery.is_some() {
+                let (only_on_ac, min_battery) = (opt.only_on_ac, opt.min_battery);
+                guard::wait_while(|| guard::check_power(only_on_ac, min_battery));
+            }
+
+            // --require-network
+            if let Some(ref host) = opt.require_network {
+                let host = host.as_ref().map_or(guard::DEFAULT_NETWORK_PROBE, String::as_str);
+                guard::wait_while(|| guard::check_network(host));
+            }
+
+            // --max-temp
+            if let Some(max_temp) = opt.max_temp {
+                guard::wait_while(|| guard::check_temperature(max_temp));
+            }
+
+            // --max-per
+            if let Some(ref mut rate_limit) = opt.max_per {
+                signal::sleep(rate_limit.take());
+            }
+
+            // --lockfile
+            let lock = match opt.lockfile {
+                Some(ref path) => match FileLock::acquire(path, opt.lock_wait || !opt.lock_skip) {
+                    Ok(Some(lock)) => Some(lock),
+                    // --lock-skip
+                    Ok(None) => {
+                        starting = true;
+                        continue;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                        error = Some(LoopError::Interrupted);
+                        break;
+                    }
+                    Err(e) => {
+                        error = Some(LoopError::Io(format!("Failed to lock {}: {}", path.display(), e)));
+                        break;
+                    }
+                },
+                None => None,
+            };
+
+            if signal::interrupted() {
                 break;
             }
+
+            // Main executor
+            let mut tmpfile = match last_output.take() {
+                Some(tmpfile) => tmpfile,
+                None => tempfile::tempfile().unwrap(),
+            };
+            tmpfile.seek(SeekFrom::Start(0)).expect("seek failed");
+            tmpfile.set_len(0).expect("truncate failed");
+            // --replace, --no-shell, --shell
+            let exec = match (&opt.replace, &opt.shell) {
+                (Some(placeholder), _) => {
+                    let argv: Vec<String> = argv.iter()
+                        .map(|word| word.replace(placeholder.as_str(), item.map_or("", String::as_str)))
+                        .collect();
+                    Exec::cmd(&argv[0]).args(&argv[1..])
+                }
+                _ if opt.no_shell => Exec::cmd(&opt.input[0]).args(&opt.input[1..]),
+                (None, Some(shell)) => Exec::cmd(&shell[0]).args(&shell[1..]).arg(joined_input),
+                (None, None) => Exec::shell(joined_input),
+            };
+            // --discard-stderr, --match-stream, --expect-file
+            // Conditions on a single stream and the expected output need to
+            // tell the lines of the two streams apart.
+            let separate = discard_stderr || opt.expect_file.is_some()
+                || opt.match_stream.as_ref().is_some_and(|stream| stream != "both");
+            let mut exec = if separate {
+                exec.stdout(Redirection::Pipe).stderr(Redirection::Pipe)
+            } else {
+                exec.stdout(Redirection::File(tmpfile.try_clone().unwrap())).stderr(Redirection::Merge)
+            };
+            // --stdin-broadcast, --consume
+            let payload = match (&stdin_broadcast, &opt.consume) {
+                (Some(payload), _) => Some(payload.clone()),
+                (None, Some(_)) => streamed.as_ref().map(|item| item.value.clone().into_bytes()),
+                (None, None) => None,
+            };
+            if payload.is_some() {
+                exec = exec.stdin(Redirection::Pipe);
+            }
+            // --sandbox-dir
+            let sandbox = match opt.sandbox_dir {
+                Some(ref template) => match Sandbox::new(template) {
+                    Ok(sandbox) => Some(sandbox),
+                    Err(e) => {
+                        error = Some(LoopError::Io(format!("Failed to create sandbox from {}: {}", template.display(), e)));
+                        break;
+                    }
+                },
+                None => None,
+            };
+            if let Some(ref sandbox) = sandbox {
+                exec = exec.cwd(sandbox.path());
+            }
+            let run_start = Instant::now();
+            // --pin-cpus
+            // The command inherits the affinity the loop has while starting it.
+            // Each job slot has its own CPU.
+            let slot = (0..jobs).find(|slot| running.iter().all(|run| run.slot != *slot)).unwrap_or(0);
+            let cpu = if cpus.is_empty() { None } else { Some(cpus[slot % cpus.len()]) };
+            let pinned = match cpu {
+                Some(cpu) => match sched::Pinned::to(cpu) {
+                    Ok(pinned) => Some(pinned),
+                    Err(e) => {
+                        error = Some(LoopError::Io(format!("Failed to pin to CPU {}: {}", cpu, e)));
+                        break;
+                    }
+                },
+                None => None,
+            };
+            let spawned = exec.popen();
+            drop(pinned);
+            let mut child = match spawned {
+                Ok(child) => child,
+                Err(e) => {
+                    error = Some(LoopError::SpawnFailed(e.to_string()));
+                    break;
+                }
+            };
+            if let (Some(payload), Some(mut stdin)) = (payload, child.stdin.take()) {
+                thread::spawn(move || stdin.write_all(&payload));
+            }
+            let streams = match (child.stdout.take(), child.stderr.take()) {
+                (Some(stdout), Some(stderr)) => Some(Tee::start(stdout, stderr, tmpfile.try_clone().unwrap())),
+                _ => None,
+            };
+            let item = item.cloned();
+            running.push_back(Running { count, slot, child, tmpfile, streams, run_start, seed, tag, sandbox, lock, streamed, key, item });
+            starting = true;
         }
 
-        // Finish if our time until has passed
-        // In this location, the loop will execute at least once,
-        // even if the start time is beyond the until time.
-        if let Some(until_time) = opt.until_time {
-            if SystemTime::now().duration_since(until_time).is_ok() {
+        // Finish the oldest iteration while the others keep running
+        let Running { count, mut child, mut tmpfile, streams, run_start, seed, tag, sandbox, lock, mut streamed, key, item, .. } =
+            match running.pop_front() {
+                Some(run) => run,
+                None => break,
+            };
+        let mut result = match watchdog::supervise(&mut child, &limits, run_start, &tmpfile) {
+            Ok(result) => result,
+            Err(e) => {
+                error = Some(LoopError::Io(format!("Failed to wait for the command: {}", e)));
+                break;
+            }
+        };
+        let run_duration = run_start.elapsed();
+
+        // --discard-stderr, --match-stream, --expect-file
+        // The output is complete once both streams ended.
+        let from_stderr = match streams.map(Tee::finish) {
+            Some(Ok(from_stderr)) => from_stderr,
+            Some(Err(e)) => {
+                error = Some(LoopError::Io(format!("Failed to read output: {}", e)));
                 break;
             }
+            None => Vec::new(),
+        };
+        let is_stderr = |number: usize| from_stderr.get(number) == Some(&true);
+
+        // --kill-if-mem, --cpu-time-limit, --timeout, --max-output
+        if let Some(ref reason) = result.killed {
+            eprintln!("Killed iteration {}: {}", count, reason);
         }
 
-        // Main executor
-        tmpfile.seek(SeekFrom::Start(0)).expect("seek failed");
-        tmpfile.set_len(0).expect("truncate failed");
-        let result = Exec::shell(joined_input)
-            .stdout(Redirection::File(tmpfile.try_clone().unwrap()))
-            .stderr(Redirection::Merge)
-            .capture().unwrap();
+        // --max-output
+        // Only the output up to the limit is kept.
+        if let Some(max_output) = opt.max_output {
+            if let (None, Some(reason)) = (&result.killed, &result.failed) {
+                eprintln!("Failed iteration {}: {}", count, reason);
+            }
+            if let Err(e) = watchdog::truncate_output(&mut tmpfile, max_output) {
+                error = Some(LoopError::Io(format!("Failed to truncate output: {}", e)));
+                break;
+            }
+        }
 
         // Print the results
-        let mut stdout = String::new();
-        tmpfile.seek(SeekFrom::Start(0)).expect("seek failed");
-        tmpfile.read_to_string(&mut stdout).expect("read failed");
-        for line in stdout.lines() {
+        let stdout = match read_output(&mut tmpfile) {
+            Ok(stdout) => stdout,
+            Err(e) => {
+                error = Some(if e.kind() == io::ErrorKind::InvalidData {
+                    LoopError::OutputDecode { iteration: count }
+                } else {
+                    LoopError::Io(format!("Failed to read output: {}", e))
+                });
+                break;
+            }
+        };
+        // --discard-stderr, --expect-file
+        // The output without the error output, which --only-last prints when
+        // it is discarded.
+        let output_only: String = if from_stderr.is_empty() {
+            stdout.clone()
+        } else {
+            stdout.lines().enumerate()
+                .filter(|&(number, _)| !is_stderr(number))
+                .map(|(_, line)| line.to_owned() + "\n")
+                .collect()
+        };
+        if discard_stderr {
+            let written = tmpfile.set_len(0)
+                .and_then(|_| tmpfile.seek(SeekFrom::Start(0)))
+                .and_then(|_| tmpfile.write_all(output_only.as_bytes()));
+            if let Err(e) = written {
+                error = Some(LoopError::Io(format!("Failed to write output: {}", e)));
+                break;
+            }
+        }
+
+        // --expect-file, --update-expect
+        // Only the output is compared, not the error output.
+        if let Some(ref path) = opt.expect_file {
+            let compared = if opt.update_expect {
+                fs::write(path, &output_only).map(|_| None)
+            } else {
+                fs::read_to_string(path).map(|expected| first_difference(&expected, &output_only))
+            };
+            match compared {
+                Ok(Some(difference)) => {
+                    eprintln!("Output of iteration {} differs from {}: {}", count, path.display(), difference);
+                    result.failed = Some(difference);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error = Some(LoopError::Io(format!("Failed to access {}: {}", path.display(), e)));
+                    break;
+                }
+            }
+        }
+
+
+        // --ack-on-success
+        if let Some(receipt) = streamed.as_mut().and_then(|item| item.receipt.take()) {
+            receipt.settle(result.success());
+        }
+        // --require-all, --require-any
+        // Text conditions are combined first, for --confirm to count them.
+        // --require-any wins, ex. over a --config file.
+        let require_all = opt.require_all && !opt.require_any;
+        let mut text_conditions = Conditions::new(require_all);
+        let mut conditions = Conditions::new(require_all);
+        let (mut contained, mut matched) = (false, false);
+        let mut suppressed = 0;
+        let (mut still_contains, mut still_matches) = (false, false);
+        let mut fired = None;
+        // --sink null
+        let printed = opt.sink.as_deref() != Some("null");
+        // --gha
+        // The output of every iteration is folded in the log.
+        if opt.gha {
+            relay(&workflow_command("group", &iteration_name(count, item.as_deref())), opt.stdout_to_stderr);
+        }
+        for (number, line) in stdout.lines().enumerate() {
             // --only-last
             // If we only want output from the last execution,
             // defer printing until later
-            if !opt.only_last {
-                println!("{}", line);
+            // --max-lines-per-sec
+            // Lines beyond the rate are dropped, and counted.
+            // --discard-stderr
+            // The error output is not printed, but conditions see it.
+            if !opt.only_last && printed && !(discard_stderr && is_stderr(number)) {
+                if !line_limit.as_mut().is_none_or(interval::RateLimit::try_take) {
+                    suppressed += 1;
+                } else if opt.tap && !opt.stdout_to_stderr {
+                    // --tap
+                    // The output is printed as diagnostics of the test.
+                    println!("# {}", line);
+                } else {
+                    relay(line, opt.stdout_to_stderr);
+                }
+            }
+
+            // --match-stream
+            // Line conditions only see the lines of one stream.
+            let stream = if is_stderr(number) { "stderr" } else { "stdout" };
+            if opt.match_stream.as_ref().is_some_and(|matched| matched != "both" && matched != stream) {
+                continue;
             }
 
             // --until-contains
             // We defer loop breaking until the entire result is printed.
-            if let Some(string) = &opt.until_contains {
-                if line.contains(string){
-                    has_matched = true;
-                }
+            // Any of several strings will do.
+            if patterns.until_contains.iter().any(|regex| regex.is_match(line)) {
+                contained = true;
             }
 
             // --until-match
-            if let Some(regex) = &opt.until_match {
-                if regex.captures(&line).is_some() {
-                    has_matched = true;
+            // Any of several regexes will do, the first one matching is told.
+            if let Some(index) = patterns.until_match.iter().position(|regex| regex.is_match(line)) {
+                matched = true;
+                if fired.is_none() {
+                    fired = Some(opt.until_match[index].clone());
                 }
             }
-        }
 
-        // --until-error
-        if let Some(error_code) = &opt.until_error {
-            match error_code {
-                ErrorCode::Any => if !result.exit_status.success() {
-                    has_matched = true;
-                },
-                ErrorCode::Code(code) =>  {
-                    if result.exit_status == ExitStatus::Exited(*code) {
-                        has_matched = true;
-                    }
+            // --until-match-count
+            // Every occurrence counts, across lines and iterations.
+            if opt.until_match_count.is_some() {
+                occurrences += patterns.until_contains.iter().chain(&patterns.until_match)
+                    .map(|regex| regex.find_iter(line).count())
+                    .sum::<usize>();
+            }
+
+            // --while-contains, --while-match
+            if patterns.while_contains.as_ref().is_some_and(|regex| regex.is_match(line)) {
+                still_contains = true;
+            }
+            if patterns.while_match.as_ref().is_some_and(|regex| regex.is_match(line)) {
+                still_matches = true;
+            }
+        }
+        match opt.until_match_count {
+            Some(needed) => text_conditions.check(occurrences >= needed),
+            None => {
+                if !opt.until_contains.is_empty() {
+                    text_conditions.check(contained);
+                }
+                if !opt.until_match.is_empty() {
+                    text_conditions.check(matched);
                 }
             }
         }
+        if opt.while_contains.is_some() {
+            text_conditions.check(!still_contains);
+        }
+        if opt.while_match.is_some() {
+            text_conditions.check(!still_matches);
+        }
+        if let (Some(regex), true) = (fired, opt.until_match.len() > 1) {
+            eprintln!("Matched --until-match '{}'", regex);
+        }
+
+        if opt.gha {
+            relay(&workflow_command("endgroup", ""), opt.stdout_to_stderr);
+        }
+        if suppressed > 0 {
+            eprintln!("[{} line(s) suppressed]", suppressed);
+        }
+
+        // --until-error
+        if let Some(ref error_code) = until_error {
+            conditions.check(match error_code {
+                ErrorCode::Any => !result.success(),
+                ErrorCode::Code(code) => result.exit_status == ExitStatus::Exited(*code),
+            });
+        }
 
         // --until-success
-        if opt.until_success && result.exit_status.success() {
-                has_matched = true;
+        if opt.until_success {
+            conditions.check(result.success());
         }
 
         // --until-fail
-        if opt.until_fail && !(result.exit_status.success()) {
-                has_matched = true;
+        if opt.until_fail {
+            conditions.check(!result.success());
         }
 
-        if opt.summary {
-            match result.exit_status {
-                ExitStatus::Exited(0)  =>  summary.successes += 1,
-                ExitStatus::Exited(n) => summary.failures.push(n),
-                _ => summary.failures.push(UNKONWN_EXIT_CODE),
+        // --stop-on-first-success-per-item, --budget
+        if let (Some(ref mut sweep), Some((key, item))) = (&mut sweep, key) {
+            sweep.record(key, item, result.success());
+        }
+
+        // --bisect
+        if let Some(ref mut bisect) = opt.bisect {
+            bisect.record(result.success());
+        }
+        // --bisect-git
+        if let Some(ref mut git_bisect) = git_bisect {
+            let verdict = match result.exit_status {
+                ExitStatus::Exited(git::SKIP_CODE) => git::Verdict::Skip,
+                _ if result.success() => git::Verdict::Good,
+                _ => git::Verdict::Bad,
+            };
+            if let Err(e) = git_bisect.record(verdict) {
+                error = Some(LoopError::Io(format!("Failed to run git bisect: {}", e)));
+                break;
             }
         }
 
-        // Finish if we matched
-        if has_matched {
-            break;
+        // --backoff
+        if let Some(ref mut backoff) = opt.backoff {
+            backoff.record(result.success());
         }
 
-        if let Some(ref previous_stdout) = previous_stdout {
-            // --until-changes
-            if opt.until_changes {
-                if *previous_stdout != stdout {
-                    break;
-                }
+        // --until-trend
+        if let Some(ref mut trend) = opt.until_trend {
+            let reason = trend.record(run_duration, result.success());
+            if let Some(ref reason) = reason {
+                eprintln!("Trend detected: {}", reason);
+            }
+            conditions.check(reason.is_some());
+        }
+
+        // --until-failure-rate
+        if let Some(ref mut failure_rate) = opt.until_failure_rate {
+            let rate = failure_rate.record(result.success());
+            if let Some(rate) = rate {
+                eprintln!("Failure rate reached {:.0}%", rate * 100.0);
+            }
+            conditions.check(rate.is_some());
+        }
+
+        // --enter, --exit
+        if let Some(ref mut hysteresis) = hysteresis {
+            let reason = stats::first_number(&stdout).and_then(|value| hysteresis.record(value, Instant::now()));
+            if let Some(ref reason) = reason {
+                eprintln!("Threshold reached: {}", reason);
+            }
+            conditions.check(reason.is_some());
+        }
+
+        // --respect-retry-after
+        if opt.respect_retry_after {
+            retry_after = parse_retry_after(&stdout, SystemTime::now());
+        }
+
+        // --checksum, --until-checksum
+        if opt.checksum.is_some() || opt.until_checksum.is_some() {
+            let checksum = sha256::hex_digest(stdout.as_bytes());
+            if opt.checksum.is_some() {
+                eprintln!("Iteration {} sha256: {}", count, checksum);
             }
+            if let Some(ref expected) = opt.until_checksum {
+                conditions.check(expected.eq_ignore_ascii_case(&checksum));
+            }
+        }
+
+        // --until-dns
+        if let Some(ref name) = opt.until_dns {
+            conditions.check(name.resolved());
+        }
 
-            // --until-same
-            if opt.until_same {
-                if *previous_stdout == stdout {
+        // --until-cmd, --until-cmd-exit
+        // The probe's output is not shown.
+        if let Some(ref probe) = opt.until_cmd {
+            match Exec::shell(probe).stdout(NullFile).stderr(NullFile).join() {
+                Ok(status) => conditions.check(status == ExitStatus::Exited(opt.until_cmd_exit.unwrap_or(0))),
+                Err(e) => {
+                    error = Some(LoopError::SpawnFailed(e.to_string()));
                     break;
                 }
             }
-        } else {
-            previous_stdout = Some(stdout);
         }
 
-        // Delay until next iteration time
-        let since = Instant::now().duration_since(loop_start);
-        if let Some(time) = opt.every.checked_sub(since) {
-            thread::sleep(time);
+        // --until-file-size, --until-file-lines
+        for condition in &mut file_conditions {
+            let reason = condition.check();
+            if let Some(ref reason) = reason {
+                eprintln!("File condition met: {}", reason);
+            }
+            conditions.check(reason.is_some());
         }
-    }
 
-    if opt.only_last {
-        let mut stdout = String::new();
-        tmpfile.seek(SeekFrom::Start(0)).expect("seek failed");
-        tmpfile.read_to_string(&mut stdout).expect("read failed");
-        for line in stdout.lines() {
-            println!("{}", line);
+        // --until-process, --while-process
+        if let Some(ref pattern) = opt.until_process {
+            conditions.check(procfs::process_running(pattern));
+        }
+        if let Some(ref pattern) = opt.while_process {
+            conditions.check(!procfs::process_running(pattern));
         }
-    }
 
-    if opt.summary {
-        summary.print()
-    }
-    process::exit(exit_status);
-}
+        // --until-ping, --while-ping
+        if let Some(ref host) = opt.until_ping {
+            conditions.check(host.reachable());
+        }
+        if let Some(ref host) = opt.while_ping {
+            conditions.check(!host.reachable());
+        }
 
-#[derive(StructOpt, Debug)]
+        // --until-mqtt
+        if let Some(ref matched) = mqtt_matched {
+            conditions.check(matched.load(Ordering::SeqCst));
+        }
+
+        // --heartbeat-file
+        if let Some(ref heartbeat_file) = opt.heartbeat_file {
+            if result.success() {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                if let Err(e) = fs::write(heartbeat_file, format!("{}\n", now.as_secs())) {
+                    eprintln!("Failed to write heartbeat file: {}", e);
+                }
+            }
+        }
+
+        // --warn-slower-than
+        if let Some(budget) = opt.warn_slower_than {
+            if run_duration > budget {
+                let message = format!("Iteration {} took {}, longer than {}", count,
+                                      format_duration(round_to_millis(run_duration)),
+                                      format_duration(budget));
+                // --gha
+                if opt.gha {
+                    println!("{}", workflow_command("warning", &message));
+                } else {
+                    warn(&message);
+                }
+                summary.slow_runs = Some(summary.slow_runs.unwrap_or(0) + 1);
+            }
+        }
+
+        // --report
+        if let Some(ref mut report) = report {
+            report.record(result.success());
+        }
+        // --junit
+        if let Some(ref mut junit) = junit {
+            junit.record(iteration_name(count, item.as_deref()), run_duration, result.failure(), &stdout);
+        }
+        // --gha
+        // Failures are annotated in the workflow run.
+        if let (true, Some(failure)) = (opt.gha, result.failure()) {
+            println!("{}", workflow_command("error", &format!("Iteration {} failed: {}", count, failure)));
+        }
+        // --tap
+        // Failures are followed by their reason as a diagnostic.
+        if opt.tap {
+            tap_tests += 1;
+            let description = iteration_name(count, item.as_deref()).replace('#', "\\#");
+            match result.failure() {
+                None => println!("ok {} - {}", tap_tests, description),
+                Some(failure) => println!("not ok {} - {}\n# {}", tap_tests, description, failure),
+            }
+        }
+
+        if opt.summary {
+            match result.exit_status {
+                _ if result.success() => summary.successes += 1,
+                ExitStatus::Exited(0) => summary.failures.push(UNKONWN_EXIT_CODE),
+                ExitStatus::Exited(n) => summary.failures.push(n),
+                _ => summary.failures.push(UNKONWN_EXIT_CODE),
+            }
+            if let (Some(failed_seeds), Some(seed)) = (summary.failed_seeds.as_mut(), seed) {
+                if !result.success() {
+                    failed_seeds.push(seed);
+                }
+            }
+            // --plot
+            if let Some(ref mut durations) = summary.durations {
+                durations.push(run_duration);
+            }
+            // --extract-kv
+            if let Some(ref mut extracted) = summary.extracted {
+                extracted.push((count, extract_kv(&stdout)));
+            }
+        }
+
+        // --aggregate, --until-mean-gt, --until-mean-lt, --until-p50-gt,
+        // --until-p95-gt, --until-p99-gt
+        if let Some(ref mut aggregate) = summary.aggregate {
+            aggregate.record(&stdout);
+            let reason = aggregate.crossed(&aggregate_limits, opt.aggregate_window);
+            if let Some(ref reason) = reason {
+                eprintln!("Aggregate limit crossed: {}", reason);
+            }
+            if !aggregate_limits.is_empty() {
+                conditions.check(reason.is_some());
+            }
+        }
+
+        // --until-changes, --until-same
+        // Neither holds on the first output.
+        if opt.until_changes {
+            text_conditions.check(previous_stdout.as_ref().is_some_and(|previous| *previous != stdout));
+        }
+        if opt.until_same {
+            text_conditions.check(previous_stdout.as_ref() == Some(&stdout));
+        }
+        // --merge
+        if let Some(ref merge_dir) = merge_dir {
+            if let Err(e) = fs::write(merge_dir.path().join(format!("{:06}", count)), &stdout) {
+                error = Some(LoopError::Io(format!("Failed to keep output for --merge: {}", e)));
+                break;
+            }
+        }
+        previous_stdout = Some(stdout);
+
+        // --confirm
+        // Text conditions have to hold in consecutive iterations.
+        let text_matched = text_conditions.met();
+        confirmations = if text_matched { confirmations + 1 } else { 0 };
+        if text_conditions.given > 0 {
+            conditions.check(text_matched && confirmations >= opt.confirm.unwrap_or(1));
+        }
+        has_matched = conditions.met();
+
+        // --arm-after
+        // Conditions met during the warm-up don't break the loop.
+        let armed = match opt.arm_after {
+            Some(Warmup::Iterations(iterations)) => count >= iterations,
+            Some(Warmup::Duration(duration)) => program_start.elapsed() >= duration,
+            None => true,
+        };
+        if !armed {
+            has_matched = false;
+        }
+
+        // --collect
+        if let (Some((cwd, root)), true) = (&collect_dirs, has_matched || !result.success()) {
+            let from = match sandbox {
+                Some(ref sandbox) => sandbox.path(),
+                None => cwd.as_path(),
+            };
+            let mut to = root.clone();
+            if let Some(ref tag) = tag {
+                to.push(path_component(tag));
+            }
+            to.push(count.to_string());
+            if let Err(e) = collect(from, &opt.collect, &to, root) {
+                eprintln!("Failed to collect artifacts: {}", e);
+            }
+        }
+
+        // --keep-failed-sandbox
+        if let Some(sandbox) = sandbox {
+            if opt.keep_failed_sandbox && !result.success() {
+                eprintln!("Kept sandbox of failed run: {}", sandbox.keep().display());
+            }
+        }
+
+        // --checkpoint-every
+        // The loop waits for checkpoints and stops when one fails.
+        for &(every, ref command) in &checkpoints {
+            if (count + 1) % every == 0 {
+                match Exec::shell(command).join() {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => {
+                        error = Some(LoopError::CheckpointFailed { command: command.to_owned(), status });
+                        break;
+                    }
+                    Err(e) => {
+                        error = Some(LoopError::SpawnFailed(e.to_string()));
+                        break;
+                    }
+                }
+            }
+        }
+
+        drop(lock);
+        last_output = Some(tmpfile);
+
+        // Finish if we matched
+        if has_matched || error.is_some() || signal::interrupted() {
+            break;
+        }
+    }
+
+    // --jobs
+    // Iterations still running when the loop ends early are stopped.
+    for mut run in running {
+        let _ = watchdog::kill(&mut run.child, limits.kill_after);
+        if let Some(receipt) = run.streamed.and_then(|item| item.receipt) {
+            receipt.settle(false);
+        }
+    }
+    if signal::interrupted() {
+        error = Some(LoopError::Interrupted);
+    }
+
+    let ran = last_output.is_some();
+    let output = match last_output {
+        Some(mut tmpfile) => read_output(&mut tmpfile).unwrap_or_default(),
+        None => String::new(),
+    };
+    if opt.only_last && opt.sink.as_deref() != Some("null") {
+        for line in output.lines() {
+            relay(line, opt.stdout_to_stderr);
+        }
+    }
+    // --copy-last
+    if opt.copy_last && ran {
+        if let Err(e) = clipboard::copy(&output) {
+            warn(&format!("Failed to copy the output to the clipboard: {}", e));
+        }
+    }
+
+    // --merge
+    if let (Some(merge), Some(merge_dir)) = (opt.merge.as_ref(), merge_dir) {
+        if error.is_none() {
+            match merge_outputs(merge, merge_dir.path()) {
+                Ok(ExitStatus::Exited(0)) => {}
+                Ok(ExitStatus::Exited(code)) => exit_status = code as i32,
+                Ok(_) => exit_status = 1,
+                Err(e) => error = Some(e),
+            }
+        }
+    }
+
+    // --tap
+    // The plan comes last, once the number of tests is known.
+    if opt.tap {
+        println!("1..{}", tap_tests);
+    }
+
+    // --report
+    if let Some(ref report) = report {
+        let reason = match (&error, stop_reason) {
+            (Some(LoopError::Interrupted), _) => "interrupted",
+            (_, Some(reason)) => reason,
+            (Some(_), None) => "error",
+            (None, None) if has_matched => "condition",
+            (None, None) => "finished",
+        };
+        let ending = report::Ending {
+            reason,
+            error: error.as_ref().map(LoopError::to_string),
+            exit_code: error.as_ref().map_or(exit_status, LoopError::exit_code),
+            last_output: &output,
+        };
+        if let Err(e) = report.write(&ending) {
+            warn(&format!("Failed to write the report: {}", e));
+        }
+    }
+    // --junit
+    if let Some(ref junit) = junit {
+        if let Err(e) = junit.write() {
+            warn(&format!("Failed to write the JUnit report: {}", e));
+        }
+    }
+
+    if opt.summary {
+        summary.dropped_items = queue.map(|queue| queue.dropped());
+        if opt.gha {
+            println!("{}", workflow_command("group", "Summary"));
+        }
+        summary.print();
+        if opt.gha {
+            println!("{}", workflow_command("endgroup", ""));
+        }
+    }
+    if let Some(sweep) = sweep {
+        sweep.print();
+    }
+    if let Some(ref bisect) = opt.bisect {
+        bisect.print();
+    }
+    // --bisect-git
+    // The revision checked out before is restored in any case.
+    if let Some(git_bisect) = git_bisect {
+        match git_bisect.finish() {
+            Ok(Some(Ok(commit))) => println!("First bad commit: {}", commit),
+            Ok(Some(Err(reason))) => eprintln!("{}", reason),
+            Ok(None) => eprintln!("Stopped before finding the first bad commit"),
+            Err(e) => warn(&format!("Failed to reset git bisect: {}", e)),
+        }
+    }
+    if let Some(error) = error {
+        error.exit(json_errors);
+    }
+    process::exit(exit_status);
+}
+
+#[derive(StructOpt, Debug)]
 #[structopt(name = "loop", author = "Rich Jones <miserlou@gmail.com>",
             about = "UNIX's missing `loop` command")]
 struct Opt {
@@ -244,16 +1605,17 @@ struct Opt {
     every: Duration,
 
     /// A comma-separated list of values, placed into 4ITEM. ex., red,green,blue
-    #[structopt(long = "for", parse(from_str = "get_values"))]
-    ffor: Option<Vec<String>>,
+    /// Given more than once, all combinations are iterated over, exported as $ITEM1, $ITEM2, ...
+    #[structopt(long = "for", parse(from_str = "get_values"), raw(number_of_values = "1"))]
+    ffor: Vec<Vec<String>>,
 
     /// Keep going until the duration has elapsed (example 1m30s)
     #[structopt(short = "d", long = "for-duration", parse(try_from_str = "parse_duration"))]
     for_duration: Option<Duration>,
 
-    /// Keep going until the output contains this string
-    #[structopt(short = "c", long = "until-contains")]
-    until_contains: Option<String>,
+    /// Keep going until the output contains this string, or any of several given
+    #[structopt(short = "c", long = "until-contains", raw(number_of_values = "1"))]
+    until_contains: Vec<String>,
 
     /// Keep going until the output changes
     #[structopt(short = "C", long = "until-changes")]
@@ -263,30 +1625,102 @@ struct Opt {
     #[structopt(short = "S", long = "until-same")]
     until_same: bool,
 
-    /// Keep going until the output matches this regular expression
-    #[structopt(short = "m", long = "until-match", parse(try_from_str = "Regex::new"))]
-    until_match: Option<Regex>,
+    /// Keep going until the output matches this regular expression, or any of several given
+    #[structopt(short = "m", long = "until-match", raw(number_of_values = "1"))]
+    until_match: Vec<String>,
+
+    /// Only stop once --until-contains or --until-match were seen this many times, across lines and iterations
+    #[structopt(long = "until-match-count", value_name = "N")]
+    until_match_count: Option<usize>,
+
+    /// Keep going while the output contains this string
+    #[structopt(long = "while-contains")]
+    while_contains: Option<String>,
+
+    /// Keep going while the output matches this regular expression
+    #[structopt(long = "while-match")]
+    while_match: Option<String>,
+
+    /// Match the text of --until-contains, --until-match, --while-contains and --while-match regardless of case
+    #[structopt(long = "ignore-case")]
+    ignore_case: bool,
 
-    /// Keep going until a future time, ex. "2018-04-20 04:20:00" (Times in UTC.)
-    #[structopt(short = "t", long = "until-time", parse(try_from_str = "parse_rfc3339_weak"))]
-    until_time: Option<SystemTime>,
+    /// Match the regular expressions of --until-match and --while-match as plain strings
+    #[structopt(long = "fixed-string")]
+    fixed_string: bool,
 
-    /// Keep going until the command exit status is the value given
-    #[structopt(short = "r", long = "until-error", parse(from_str = "get_error_code"))]
-    until_error: Option<ErrorCode>,
+    /// Keep going until a future time, ex. "2018-04-20 04:20:00", "friday 17:00" or +2h (local time)
+    #[structopt(short = "t", long = "until-time", parse(try_from_str = "parse_until_time"))]
+    until_time: Option<UntilTime>,
+
+    /// Read the dates and times of --until-time without a UTC offset in UTC instead of local time
+    #[structopt(long = "utc", raw(requires = r#""until_time""#))]
+    utc: bool,
+
+    /// Keep going until the command exit status is non-zero, or the value given
+    #[structopt(short = "r", long = "until-error")]
+    until_error: Option<Option<String>>,
 
     /// Keep going until the command exit status is zero
-    #[structopt(short = "s", long = "until-success")]
+    #[structopt(short = "s", long = "until-success", raw(visible_alias = r#""while-fail""#))]
     until_success: bool,
 
     /// Keep going until the command exit status is non-zero
-    #[structopt(short = "f", long = "until-fail")]
+    #[structopt(short = "f", long = "until-fail", raw(visible_alias = r#""while-success""#))]
     until_fail: bool,
 
+    /// Only stop once all the stop conditions given hold after the same iteration
+    #[structopt(long = "require-all")]
+    require_all: bool,
+
+    /// Stop once any of the stop conditions given holds, the default, even with --require-all
+    #[structopt(long = "require-any")]
+    require_any: bool,
+
     /// Only print the output of the last execution of the command
     #[structopt(short = "l", long = "only-last")]
     only_last: bool,
 
+    /// Write a JSON report of how the loop went to this file when it ends, however it ends
+    #[structopt(long = "report", parse(from_os_str))]
+    report: Option<PathBuf>,
+
+    /// Write every iteration as a test case, with its duration and failure, to this JUnit XML file when the loop ends
+    #[structopt(long = "junit", parse(from_os_str))]
+    junit: Option<PathBuf>,
+
+    /// Print every iteration as a test in TAP, the Test Anything Protocol, with the output as diagnostics
+    #[structopt(long = "tap", raw(conflicts_with = r#""only_last""#))]
+    tap: bool,
+
+    /// Print GitHub Actions workflow commands, grouping the output of every iteration and annotating failures
+    #[structopt(long = "gha", raw(conflicts_with_all = r#"&["only_last", "tap"]"#))]
+    gha: bool,
+
+    /// Where to print the command's output: stdout, the default, or null to only check the conditions on it
+    #[structopt(long = "sink", raw(possible_values = r#"&["stdout", "null"]"#))]
+    sink: Option<String>,
+
+    /// Print the command's error output along with its output, the default, even with --discard-stderr
+    #[structopt(long = "stderr-to-stdout")]
+    stderr_to_stdout: bool,
+
+    /// Print the command's output on stderr instead of stdout, leaving stdout to the loop's own reports
+    #[structopt(long = "stdout-to-stderr")]
+    stdout_to_stderr: bool,
+
+    /// Don't print the command's error output, which conditions still see
+    #[structopt(long = "discard-stderr")]
+    discard_stderr: bool,
+
+    /// Which of the command's streams line conditions like --until-contains see: stdout, stderr or both, the default
+    #[structopt(long = "match-stream", raw(possible_values = r#"&["stdout", "stderr", "both"]"#))]
+    match_stream: Option<String>,
+
+    /// Print at most N lines of output per second, dropping the others, ex. of a chatty command
+    #[structopt(long = "max-lines-per-sec", value_name = "N", raw(conflicts_with = r#""only_last""#))]
+    max_lines_per_sec: Option<u32>,
+
     /// Read from standard input
     #[structopt(short = "i", long = "stdin")]
     stdin: bool,
@@ -299,12 +1733,706 @@ struct Opt {
     #[structopt(long = "summary")]
     summary: bool,
 
+    /// Export a variable rendered from a template each iteration, ex. 'TS={now:%Y%m%dT%H%M%S}'
+    #[structopt(long = "env-template", parse(try_from_str = "parse_env_template"),
+                raw(number_of_values = "1"))]
+    env_template: Vec<EnvTemplate>,
+
+    /// Export the variables of a file of KEY=TEMPLATE lines each iteration, ex. 'OUT={item}.log'
+    #[structopt(long = "env-template-file", parse(try_from_str = "parse_env_template_file"))]
+    env_template_file: Option<Vec<EnvTemplate>>,
+
+    /// Write this string, or the contents of the file given as @path, to the command's stdin every iteration
+    #[structopt(long = "stdin-broadcast")]
+    stdin_broadcast: Option<String>,
+
+    /// Run every iteration in a fresh copy of this directory
+    #[structopt(long = "sandbox-dir", parse(from_os_str))]
+    sandbox_dir: Option<PathBuf>,
+
+    /// Keep the sandbox directory of iterations that fail
+    #[structopt(long = "keep-failed-sandbox")]
+    keep_failed_sandbox: bool,
+
+    /// Copy files matching this glob into --collect-dir when an iteration fails or breaks the loop
+    #[structopt(long = "collect", raw(number_of_values = "1", requires = r#""collect_dir""#))]
+    collect: Vec<String>,
+
+    /// Directory receiving a folder of collected artifacts per iteration
+    #[structopt(long = "collect-dir", parse(from_os_str))]
+    collect_dir: Option<PathBuf>,
+
+    /// Hold back iterations while less space is free, ex. 1G or 500M:/var/log
+    #[structopt(long = "min-free-space", parse(try_from_str = "parse_free_space"))]
+    min_free_space: Option<FreeSpace>,
+
+    /// What to do when --min-free-space is not met
+    #[structopt(long = "on-low-space", default_value = "pause",
+                raw(possible_values = r#"&["pause", "abort"]"#))]
+    on_low_space: String,
+
+    /// Hold back iterations while the one minute load average is above this
+    #[structopt(long = "max-load")]
+    max_load: Option<f64>,
+
+    /// Hold back iterations while the system CPU usage is above this, ex. 80%
+    #[structopt(long = "max-cpu", parse(try_from_str = "parse_cpu_usage"))]
+    max_cpu: Option<CpuUsage>,
+
+    /// Hold back iterations while running on battery
+    #[structopt(long = "only-on-ac")]
+    only_on_ac: bool,
+
+    /// Hold back iterations while discharging below this battery level, ex. 30%
+    #[structopt(long = "min-battery", parse(try_from_str = "parse_battery"))]
+    min_battery: Option<f64>,
+
+    /// Hold back iterations while this host (default 1.1.1.1:53) cannot be reached
+    #[structopt(long = "require-network")]
+    require_network: Option<Option<String>>,
+
+    /// Hold back iterations while any CPU/GPU sensor is hotter than this, ex. 85C
+    #[structopt(long = "max-temp", parse(try_from_str = "parse_temperature"))]
+    max_temp: Option<f64>,
+
+    /// Hold an exclusive lock on this file while each iteration runs
+    #[structopt(long = "lockfile", parse(from_os_str))]
+    lockfile: Option<PathBuf>,
+
+    /// Wait for the --lockfile to become free (the default)
+    #[structopt(long = "lock-wait", raw(conflicts_with = r#""lock_skip""#))]
+    lock_wait: bool,
+
+    /// Skip the iteration if the --lockfile is held by someone else
+    #[structopt(long = "lock-skip")]
+    lock_skip: bool,
+
+    /// Refuse to start if another loop is running the same command for this user
+    #[structopt(long = "single-instance")]
+    single_instance: bool,
+
+    /// Like --single-instance, but terminate the other loop and take its place
+    #[structopt(long = "take-over")]
+    take_over: bool,
+
+    /// Wait until this time, read like --until-time, or until this file exists, before the first iteration
+    #[structopt(long = "sync-start")]
+    sync_start: Option<String>,
+
+    /// Keep going until a metric degrades, ex. 'duration +20% over 10' or 'failure-rate +30% over 20'
+    #[structopt(long = "until-trend", parse(try_from_str = "parse_trend"))]
+    until_trend: Option<Trend>,
+
+    /// Keep going until this share of the last N iterations failed, ex. 0.5/20
+    #[structopt(long = "until-failure-rate", parse(try_from_str = "parse_failure_rate"))]
+    until_failure_rate: Option<FailureRate>,
+
+    /// Kill an iteration, counting it as failed, once it output more than this, ex. 10M, keeping the output up to it
+    #[structopt(long = "max-output", parse(try_from_str = "parse_size"))]
+    max_output: Option<u64>,
+
+    /// Kill an iteration, counting it as failed, once its processes use more memory than this, ex. 2G (Linux only)
+    #[structopt(long = "kill-if-mem", parse(try_from_str = "parse_size"))]
+    kill_if_mem: Option<u64>,
+
+    /// Kill an iteration, counting it as failed, once its processes used this much CPU time, ex. 30s (Linux only)
+    #[structopt(long = "cpu-time-limit", parse(try_from_str = "parse_duration"))]
+    cpu_time_limit: Option<Duration>,
+
+    /// Kill an iteration, counting it as failed, once it ran longer than this, ex. 10s
+    #[structopt(long = "timeout", parse(try_from_str = "parse_duration"))]
+    timeout: Option<Duration>,
+
+    /// Send SIGTERM first when killing an iteration, and SIGKILL only after this grace period, ex. 5s
+    #[structopt(long = "kill-after", parse(try_from_str = "parse_duration"))]
+    kill_after: Option<Duration>,
+
+    /// Touch this file, writing the current Unix time, after every successful iteration
+    #[structopt(long = "heartbeat-file", parse(from_os_str))]
+    heartbeat_file: Option<PathBuf>,
+
+    /// Print a warning whenever an iteration takes longer than this, ex. 500ms
+    #[structopt(long = "warn-slower-than", parse(try_from_str = "parse_duration"))]
+    warn_slower_than: Option<Duration>,
+
+    /// What to do with iterations missed while the system was suspended: run, skip or reset the schedule
+    #[structopt(long = "after-resume", parse(try_from_str = "parse_after_resume"))]
+    after_resume: Option<AfterResume>,
+
+    /// Read options from a JSON or TOML file; options on the command line take precedence
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Read options from a JSON or TOML specification on stdin before looping
+    #[structopt(long = "config-stdin")]
+    config_stdin: bool,
+
+    /// Print the resolved configuration as TOML and as a command line, then exit
+    #[structopt(long = "print-config")]
+    print_config: bool,
+
+    /// How to report errors of the loop itself on stderr: text or json
+    #[structopt(long = "errors", default_value = "text", raw(possible_values = r#"&["text", "json"]"#))]
+    errors: String,
+
+    /// Iterate over the numbers from START to END included, ex. 1..1000:5
+    #[structopt(long = "for-range", parse(try_from_str = "parse_range"),
+                raw(conflicts_with_all = r#"&["ffor", "for_file"]"#))]
+    for_range: Option<Range>,
+
+    /// Iterate over the rows of a CSV file, exporting the columns as $ITEM1..N
+    #[structopt(long = "for-csv", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["ffor", "for_file", "for_range", "for_cmd", "stdin"]"#))]
+    for_csv: Option<PathBuf>,
+
+    /// Take the column names from the first row of --for-csv, also exporting the columns as $ITEM_<name>
+    #[structopt(long = "csv-header", raw(requires = r#""for_csv""#))]
+    csv_header: bool,
+
+    /// Iterate over the lines of the clipboard
+    #[structopt(long = "for-clipboard",
+                raw(conflicts_with_all = r#"&["ffor", "for_file", "for_range", "for_cmd", "for_csv", "for_jsonl", "stdin"]"#))]
+    for_clipboard: bool,
+
+    /// Copy the output of the last iteration to the clipboard when the loop ends
+    #[structopt(long = "copy-last")]
+    copy_last: bool,
+
+    /// Iterate over the objects of a JSON Lines file, exporting each --field
+    #[structopt(long = "for-jsonl", parse(from_os_str),
+                raw(conflicts_with_all = r#"&["ffor", "for_file", "for_range", "for_cmd", "for_csv", "stdin"]"#))]
+    for_jsonl: Option<PathBuf>,
+
+    /// Export this field of --for-jsonl objects as $ITEM_<field>, ex. user.name
+    #[structopt(long = "field", raw(number_of_values = "1", requires = r#""for_jsonl""#))]
+    field: Vec<String>,
+
+    /// Separate items from stdin, --for-file and --for-cmd by NUL instead of newlines, as output by find -print0
+    #[structopt(short = "0", long = "null")]
+    null: bool,
+
+    /// Stream items from the output lines of this command, ex. 'ls *.log'
+    #[structopt(long = "for-cmd",
+                raw(conflicts_with_all = r#"&["ffor", "for_file", "for_range", "consume", "for_redis", "mqtt_trigger", "on_dbus"]"#))]
+    for_cmd: Option<String>,
+
+    /// Run the --for-cmd command again after its items, as long as it outputs any
+    #[structopt(long = "restart-for-cmd", raw(requires = r#""for_cmd""#))]
+    restart_for_cmd: bool,
+
+    /// Stream items from the lines of this file, leaving stdin to the command
+    /// Given more than once, or with --for, all combinations of the lists are iterated over
+    #[structopt(long = "for-file", parse(from_os_str),
+                raw(number_of_values = "1",
+                    conflicts_with_all = r#"&["consume", "for_redis", "mqtt_trigger", "on_dbus", "stdin"]"#))]
+    for_file: Vec<PathBuf>,
+
+    /// Binary-search the integers from LO to HI for the first one the command fails with, ex. 1..1000
+    #[structopt(long = "bisect", value_name = "LO..HI", parse(try_from_str = "parse_bisect"),
+                raw(conflicts_with_all = r#"&["ffor", "for_file", "for_range", "for_cmd", "for_csv", "for_jsonl",
+                                              "for_clipboard", "cycle", "jobs"]"#))]
+    bisect: Option<Bisect>,
+
+    /// Find the first bad commit from GOOD to BAD with git bisect, the command exiting with 0 for good, 125 to skip
+    #[structopt(long = "bisect-git", value_name = "GOOD..BAD", parse(try_from_str = "git::parse_range"),
+                raw(conflicts_with_all = r#"&["ffor", "for_file", "for_range", "for_cmd", "for_csv", "for_jsonl",
+                                              "for_clipboard", "cycle", "jobs", "bisect"]"#))]
+    bisect_git: Option<git::Range>,
+
+    /// Report statistics in the --summary of the number captured by this regex from every output, ex. 'time=([0-9.]+)'
+    #[structopt(long = "aggregate", parse(try_from_str = "parse_aggregate"))]
+    aggregate: Option<Aggregate>,
+
+    /// Compute the statistics of the --until-mean-gt and similar conditions over the latest N numbers only
+    #[structopt(long = "aggregate-window", raw(requires = r#""aggregate""#))]
+    aggregate_window: Option<usize>,
+
+    /// Keep going until the mean of the --aggregate numbers is above this
+    #[structopt(long = "until-mean-gt", raw(requires = r#""aggregate""#))]
+    until_mean_gt: Option<f64>,
+
+    /// Keep going until the mean of the --aggregate numbers is below this
+    #[structopt(long = "until-mean-lt", raw(requires = r#""aggregate""#))]
+    until_mean_lt: Option<f64>,
+
+    /// Keep going until the median of the --aggregate numbers is above this
+    #[structopt(long = "until-p50-gt", raw(requires = r#""aggregate""#))]
+    until_p50_gt: Option<f64>,
+
+    /// Keep going until the 95th percentile of the --aggregate numbers is above this
+    #[structopt(long = "until-p95-gt", raw(requires = r#""aggregate""#))]
+    until_p95_gt: Option<f64>,
+
+    /// Keep going until the 99th percentile of the --aggregate numbers is above this
+    #[structopt(long = "until-p99-gt", raw(requires = r#""aggregate""#))]
+    until_p99_gt: Option<f64>,
+
+    /// Plot the --aggregate numbers, or else the durations of the iterations, in the --summary
+    #[structopt(long = "plot", raw(requires = r#""summary""#))]
+    plot: bool,
+
+    /// Collect key=value pairs and JSON objects from the output, shown as a table in the --summary
+    #[structopt(long = "extract-kv", raw(requires = r#""summary""#))]
+    extract_kv: bool,
+
+    /// Pass up to N items to each iteration, as $ITEMS joined by spaces and as $ITEM1 to $ITEM<N>
+    #[structopt(long = "batch", parse(try_from_str = "parse_batch"), raw(conflicts_with = r#""for_range""#))]
+    batch: Option<usize>,
+
+    /// Start over from the first item after the last one, looping until another condition ends it
+    #[structopt(long = "cycle",
+                raw(conflicts_with_all = r#"&["consume", "for_redis", "mqtt_trigger", "on_dbus", "for_cmd"]"#))]
+    cycle: bool,
+
+    /// Shuffle the items again for every pass of --cycle, repeatable with --seed
+    #[structopt(long = "reshuffle-each-cycle", raw(requires = r#""cycle""#))]
+    reshuffle_each_cycle: bool,
+
+    /// Start every pass of --cycle N items further into the list than the previous one
+    #[structopt(long = "rotate", value_name = "N", raw(requires = r#""cycle""#))]
+    rotate: Option<usize>,
+
+    /// Run the items in a random order, repeatable by giving the same seed
+    #[structopt(long = "shuffle", value_name = "SEED")]
+    shuffle: Option<Option<u64>>,
+
+    /// Pair the values of several --for and --for-file lists positionally, up to the shortest list
+    #[structopt(long = "zip")]
+    zip: bool,
+
+    /// Consume messages from nats://host[:port]/subject as items, also written to the command's stdin
+    #[structopt(long = "consume", parse(try_from_str = "nats::parse_source"))]
+    consume: Option<nats::Source>,
+
+    /// Acknowledge consumed messages after successful iterations, and have failed ones redelivered
+    #[structopt(long = "ack-on-success", raw(requires = r#""consume""#))]
+    ack_on_success: bool,
+
+    /// Pop items off a Redis list, ex. list:jobs, waiting for more when it is empty
+    #[structopt(long = "for-redis", parse(try_from_str = "redis::parse_source"),
+                raw(conflicts_with = r#""consume""#))]
+    for_redis: Option<redis::Source>,
+
+    /// Address of the Redis server of --for-redis (default 127.0.0.1:6379)
+    #[structopt(long = "redis-address", raw(requires = r#""for_redis""#))]
+    redis_address: Option<String>,
+
+    /// Push the items of failed iterations to this Redis list
+    #[structopt(long = "dead-letter", raw(requires = r#""for_redis""#))]
+    dead_letter: Option<String>,
+
+    /// Run an iteration per message received on this MQTT topic, with the payload as the item
+    #[structopt(long = "mqtt-trigger", raw(conflicts_with_all = r#"&["consume", "for_redis"]"#))]
+    mqtt_trigger: Option<String>,
+
+    /// Print a digest of the output of every iteration on stderr
+    #[structopt(long = "checksum", raw(possible_values = r#"&["sha256"]"#))]
+    checksum: Option<String>,
+
+    /// Keep going until the SHA-256 digest of the output is this hex string
+    #[structopt(long = "until-checksum")]
+    until_checksum: Option<String>,
+
+    /// Count iterations whose output differs from this file as failed
+    #[structopt(long = "expect-file", parse(from_os_str))]
+    expect_file: Option<PathBuf>,
+
+    /// Write the output of every iteration to --expect-file instead of comparing it
+    #[structopt(long = "update-expect", raw(requires = r#""expect_file""#))]
+    update_expect: bool,
+
+    /// Start iterations when this crontab expression fires instead of --every, ex. '*/5 * * * *' (local time)
+    #[structopt(long = "cron", parse(try_from_str = "cron::parse"),
+                raw(conflicts_with_all = r#"&["every", "every_aligned", "every_start", "jitter", "backoff", "after_resume"]"#))]
+    cron: Option<cron::Schedule>,
+
+    /// Start at most N iterations per duration, ex. 10/1m, allowing bursts of up to N
+    #[structopt(long = "max-per", value_name = "N/DURATION", parse(try_from_str = "interval::parse_rate_limit"))]
+    max_per: Option<interval::RateLimit>,
+
+    /// Export CHAOS_FAIL=1 with this probability, else 0, for the command to test its handling of failures, ex. 20%
+    #[structopt(long = "chaos", value_name = "PERCENT", parse(try_from_str = "parse_chaos"))]
+    chaos: Option<f64>,
+
+    /// Export a random CHAOS_DELAY_MS of up to this duration, for the command to stall for
+    #[structopt(long = "chaos-delay", parse(try_from_str = "parse_duration"), raw(requires = r#""chaos""#))]
+    chaos_delay: Option<Duration>,
+
+    /// Add a random delay of up to this duration between iterations
+    #[structopt(long = "chaos-wait", parse(try_from_str = "parse_duration"), raw(requires = r#""chaos""#))]
+    chaos_wait: Option<Duration>,
+
+    /// Start iterations at multiples of --every since the first one, skipping those missed by long iterations
+    #[structopt(long = "every-start",
+                raw(conflicts_with_all = r#"&["every_aligned", "jitter", "backoff", "after_resume"]"#))]
+    every_start: bool,
+
+    /// Start iterations at multiples of --every on the clock, ex. at the start of every minute with --every 1m
+    #[structopt(long = "every-aligned", raw(conflicts_with_all = r#"&["after_resume", "jitter"]"#))]
+    every_aligned: bool,
+
+    /// Randomly wait up to this much longer or shorter than --every, ex. 5s or 10%
+    #[structopt(long = "jitter", parse(try_from_str = "interval::parse_jitter"))]
+    jitter: Option<interval::Jitter>,
+
+    /// Skip the items which succeeded already, telling them apart by $ITEM1 when there is one
+    #[structopt(long = "stop-on-first-success-per-item")]
+    stop_on_first_success_per_item: bool,
+
+    /// Stop the loop once this much time was spent, reporting the results per item
+    #[structopt(long = "budget", parse(try_from_str = "parse_duration"))]
+    budget: Option<Duration>,
+
+    /// Multiply the --every interval, or 1s without it, by FACTOR after every failure in a row, up to MAX or 1h, ex. 2..5m
+    #[structopt(long = "backoff", parse(try_from_str = "interval::parse_backoff"))]
+    backoff: Option<interval::Backoff>,
+
+    /// Wait as long as a Retry-After header in the output asks for before the next iteration, instead of --every
+    #[structopt(long = "respect-retry-after")]
+    respect_retry_after: bool,
+
+    /// Keep going until a DNS name resolves, optionally to this address, ex. api.example.com=10.0.0.7
+    #[structopt(long = "until-dns", parse(try_from_str = "parse_dns_name"))]
+    until_dns: Option<DnsName>,
+
+    /// Keep going until this command, run after every iteration, succeeds, ex. 'curl -fsS localhost:8080/health'
+    #[structopt(long = "until-cmd")]
+    until_cmd: Option<String>,
+
+    /// Keep going until the --until-cmd command exits with this code instead of 0
+    #[structopt(long = "until-cmd-exit", value_name = "CODE", raw(requires = r#""until_cmd""#))]
+    until_cmd_exit: Option<u32>,
+
+    /// Keep going until the size of a file compares to SIZE, by <, <=, >, >=, == or stalls (stops growing before SIZE)
+    #[structopt(long = "until-file-size", raw(number_of_values = "3", value_names = r#"&["PATH", "OP", "SIZE"]"#))]
+    until_file_size: Vec<String>,
+
+    /// Keep going until the number of lines of a file compares to COUNT, like --until-file-size
+    #[structopt(long = "until-file-lines", raw(number_of_values = "3", value_names = r#"&["PATH", "OP", "COUNT"]"#))]
+    until_file_lines: Vec<String>,
+
+    /// Keep going until a process whose name matches this regex runs, ex. nginx or 'python3?' (Linux only)
+    #[structopt(long = "until-process", parse(try_from_str = "parse_process_name"))]
+    until_process: Option<Regex>,
+
+    /// Keep going while a process whose name matches this regex runs (Linux only)
+    #[structopt(long = "while-process", parse(try_from_str = "parse_process_name"))]
+    while_process: Option<Regex>,
+
+    /// Keep going until a host answers a ping, or a TCP connection to HOST:PORT
+    #[structopt(long = "until-ping", parse(try_from_str = "ping::parse_host"))]
+    until_ping: Option<ping::Host>,
+
+    /// Keep going while a host answers a ping, or a TCP connection to HOST:PORT
+    #[structopt(long = "while-ping", parse(try_from_str = "ping::parse_host"))]
+    while_ping: Option<ping::Host>,
+
+    /// Keep going until this MQTT topic received this payload
+    #[structopt(long = "until-mqtt", raw(number_of_values = "2", value_names = r#"&["TOPIC", "PAYLOAD"]"#))]
+    until_mqtt: Vec<String>,
+
+    /// Address of the MQTT broker (default 127.0.0.1:1883)
+    #[structopt(long = "mqtt-broker")]
+    mqtt_broker: Option<String>,
+
+    /// Run an iteration per D-Bus signal matching this rule, ex. "type='signal',member='PrepareForSleep'"
+    #[structopt(long = "on-dbus", raw(conflicts_with_all = r#"&["consume", "for_redis", "mqtt_trigger"]"#))]
+    on_dbus: Option<String>,
+
+    /// Watch the session bus with --on-dbus instead of the system bus
+    #[structopt(long = "dbus-session", raw(requires = r#""on_dbus""#))]
+    dbus_session: bool,
+
+    /// Queue at most N streamed items; with it, items from stdin are streamed too
+    #[structopt(long = "queue-max")]
+    queue_max: Option<usize>,
+
+    /// What to do with new items while the queue is full: drop-oldest, drop-newest or block (default)
+    #[structopt(long = "on-full", parse(try_from_str = "parse_on_full"), raw(requires = r#""queue_max""#))]
+    on_full: Option<OnFull>,
+
+    /// Run the command with this niceness increment, ex. 10
+    #[structopt(long = "nice", raw(allow_hyphen_values = "true"))]
+    nice: Option<i32>,
+
+    /// Pin the command to a single CPU, or each of the --jobs to its own (Linux only)
+    #[structopt(long = "pin-cpus")]
+    pin_cpus: bool,
+
+    /// Finally run this command on all outputs, concatenated on its stdin and as files in $LOOP_OUTPUT_DIR
+    #[structopt(long = "merge")]
+    merge: Option<String>,
+
+    /// Break once the first number in the output crossed a threshold and stayed there, ex. '>90 for 5m'
+    #[structopt(long = "enter", parse(try_from_str = "parse_threshold"))]
+    enter: Option<Threshold>,
+
+    /// Threshold resetting --enter, ex. '<70'; by default --enter no longer holding
+    #[structopt(long = "exit", parse(try_from_str = "parse_threshold"), raw(requires = r#""enter""#))]
+    exit: Option<Threshold>,
+
+    /// Only break on --until-contains, --until-match, --until-changes or --until-same once they held in N iterations in a row
+    #[structopt(long = "confirm")]
+    confirm: Option<usize>,
+
+    /// Only evaluate until conditions after a warm-up of N iterations or a duration, ex. 5 or 30s
+    #[structopt(long = "arm-after", parse(try_from_str = "parse_warmup"))]
+    arm_after: Option<Warmup>,
+
+    /// Run a command after every N iterations, ex. 100 './flush.sh', stopping the loop if it fails
+    #[structopt(long = "checkpoint-every", raw(number_of_values = "2", value_names = r#"&["N", "COMMAND"]"#))]
+    checkpoint_every: Vec<String>,
+
+    /// Tag every iteration from a template, ex. '{item}-{now:%H}', exported as $LOOP_TAG and {tag}
+    #[structopt(long = "tag", parse(try_from_str = "Template::parse"))]
+    tag: Option<Template>,
+
+    /// Run up to N iterations at once, finishing them in order
+    #[structopt(short = "j", long = "jobs", raw(conflicts_with = r#""lockfile""#))]
+    jobs: Option<usize>,
+
+    /// Base seed; each iteration exports this plus its index as $LOOP_SEED
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Run the command and its arguments directly instead of joining them into a shell command
+    #[structopt(short = "x", long = "no-shell")]
+    no_shell: bool,
+
+    /// Replace this string, ex. {}, with the item in the arguments of the command, which runs without a shell
+    #[structopt(short = "I", long = "replace", raw(conflicts_with = r#""shell""#))]
+    replace: Option<String>,
+
+    /// Run the command with this shell instead of sh, ex. zsh, pwsh or 'bash -euo pipefail -c'
+    #[structopt(long = "shell", parse(try_from_str = "parse_shell"), raw(conflicts_with = r#""no_shell""#))]
+    shell: Option<Vec<String>>,
+
     /// The command to be looped
     #[structopt(raw(multiple="true"))]
     input: Vec<String>
 
 }
 
+/// The options of `Opt` which settings can be given for, in the order they
+/// are declared. Keep it in sync with `Opt`.
+const OPTIONS: &config::Options = {
+    use config::Kind::*;
+    &[
+        ("num", "num", Single),
+        ("count-by", "count_by", Single),
+        ("offset", "offset", Single),
+        ("every", "every", Single),
+        ("for", "ffor", Multiple(1)),
+        ("for-duration", "for_duration", Single),
+        ("until-contains", "until_contains", Multiple(1)),
+        ("until-changes", "until_changes", Flag),
+        ("until-same", "until_same", Flag),
+        ("until-match", "until_match", Multiple(1)),
+        ("until-match-count", "until_match_count", Single),
+        ("while-contains", "while_contains", Single),
+        ("while-match", "while_match", Single),
+        ("ignore-case", "ignore_case", Flag),
+        ("fixed-string", "fixed_string", Flag),
+        ("until-time", "until_time", Single),
+        ("utc", "utc", Flag),
+        ("until-error", "until_error", Single),
+        ("until-success", "until_success", Flag),
+        ("until-fail", "until_fail", Flag),
+        ("require-all", "require_all", Flag),
+        ("require-any", "require_any", Flag),
+        ("only-last", "only_last", Flag),
+        ("report", "report", Single),
+        ("junit", "junit", Single),
+        ("tap", "tap", Flag),
+        ("gha", "gha", Flag),
+        ("sink", "sink", Single),
+        ("stderr-to-stdout", "stderr_to_stdout", Flag),
+        ("stdout-to-stderr", "stdout_to_stderr", Flag),
+        ("discard-stderr", "discard_stderr", Flag),
+        ("match-stream", "match_stream", Single),
+        ("max-lines-per-sec", "max_lines_per_sec", Single),
+        ("stdin", "stdin", Flag),
+        ("error-duration", "error_duration", Flag),
+        ("summary", "summary", Flag),
+        ("env-template", "env_template", Multiple(1)),
+        ("env-template-file", "env_template_file", Single),
+        ("stdin-broadcast", "stdin_broadcast", Single),
+        ("sandbox-dir", "sandbox_dir", Single),
+        ("keep-failed-sandbox", "keep_failed_sandbox", Flag),
+        ("collect", "collect", Multiple(1)),
+        ("collect-dir", "collect_dir", Single),
+        ("min-free-space", "min_free_space", Single),
+        ("on-low-space", "on_low_space", Single),
+        ("max-load", "max_load", Single),
+        ("max-cpu", "max_cpu", Single),
+        ("only-on-ac", "only_on_ac", Flag),
+        ("min-battery", "min_battery", Single),
+        ("require-network", "require_network", Single),
+        ("max-temp", "max_temp", Single),
+        ("lockfile", "lockfile", Single),
+        ("lock-wait", "lock_wait", Flag),
+        ("lock-skip", "lock_skip", Flag),
+        ("single-instance", "single_instance", Flag),
+        ("take-over", "take_over", Flag),
+        ("sync-start", "sync_start", Single),
+        ("until-trend", "until_trend", Single),
+        ("until-failure-rate", "until_failure_rate", Single),
+        ("max-output", "max_output", Single),
+        ("kill-if-mem", "kill_if_mem", Single),
+        ("cpu-time-limit", "cpu_time_limit", Single),
+        ("timeout", "timeout", Single),
+        ("kill-after", "kill_after", Single),
+        ("heartbeat-file", "heartbeat_file", Single),
+        ("warn-slower-than", "warn_slower_than", Single),
+        ("after-resume", "after_resume", Single),
+        ("config", "config", Single),
+        ("config-stdin", "config_stdin", Flag),
+        ("print-config", "print_config", Flag),
+        ("errors", "errors", Single),
+        ("for-range", "for_range", Single),
+        ("for-csv", "for_csv", Single),
+        ("csv-header", "csv_header", Flag),
+        ("for-clipboard", "for_clipboard", Flag),
+        ("copy-last", "copy_last", Flag),
+        ("for-jsonl", "for_jsonl", Single),
+        ("field", "field", Multiple(1)),
+        ("null", "null", Flag),
+        ("for-cmd", "for_cmd", Single),
+        ("restart-for-cmd", "restart_for_cmd", Flag),
+        ("for-file", "for_file", Multiple(1)),
+        ("bisect", "bisect", Single),
+        ("bisect-git", "bisect_git", Single),
+        ("aggregate", "aggregate", Single),
+        ("aggregate-window", "aggregate_window", Single),
+        ("until-mean-gt", "until_mean_gt", Single),
+        ("until-mean-lt", "until_mean_lt", Single),
+        ("until-p50-gt", "until_p50_gt", Single),
+        ("until-p95-gt", "until_p95_gt", Single),
+        ("until-p99-gt", "until_p99_gt", Single),
+        ("plot", "plot", Flag),
+        ("extract-kv", "extract_kv", Flag),
+        ("batch", "batch", Single),
+        ("cycle", "cycle", Flag),
+        ("reshuffle-each-cycle", "reshuffle_each_cycle", Flag),
+        ("rotate", "rotate", Single),
+        ("shuffle", "shuffle", Single),
+        ("zip", "zip", Flag),
+        ("consume", "consume", Single),
+        ("ack-on-success", "ack_on_success", Flag),
+        ("for-redis", "for_redis", Single),
+        ("redis-address", "redis_address", Single),
+        ("dead-letter", "dead_letter", Single),
+        ("mqtt-trigger", "mqtt_trigger", Single),
+        ("checksum", "checksum", Single),
+        ("until-checksum", "until_checksum", Single),
+        ("expect-file", "expect_file", Single),
+        ("update-expect", "update_expect", Flag),
+        ("cron", "cron", Single),
+        ("max-per", "max_per", Single),
+        ("chaos", "chaos", Single),
+        ("chaos-delay", "chaos_delay", Single),
+        ("chaos-wait", "chaos_wait", Single),
+        ("every-start", "every_start", Flag),
+        ("every-aligned", "every_aligned", Flag),
+        ("jitter", "jitter", Single),
+        ("stop-on-first-success-per-item", "stop_on_first_success_per_item", Flag),
+        ("budget", "budget", Single),
+        ("backoff", "backoff", Single),
+        ("respect-retry-after", "respect_retry_after", Flag),
+        ("until-dns", "until_dns", Single),
+        ("until-cmd", "until_cmd", Single),
+        ("until-cmd-exit", "until_cmd_exit", Single),
+        ("until-file-size", "until_file_size", Multiple(3)),
+        ("until-file-lines", "until_file_lines", Multiple(3)),
+        ("until-process", "until_process", Single),
+        ("while-process", "while_process", Single),
+        ("until-ping", "until_ping", Single),
+        ("while-ping", "while_ping", Single),
+        ("until-mqtt", "until_mqtt", Multiple(2)),
+        ("mqtt-broker", "mqtt_broker", Single),
+        ("on-dbus", "on_dbus", Single),
+        ("dbus-session", "dbus_session", Flag),
+        ("queue-max", "queue_max", Single),
+        ("on-full", "on_full", Single),
+        ("nice", "nice", Single),
+        ("pin-cpus", "pin_cpus", Flag),
+        ("merge", "merge", Single),
+        ("enter", "enter", Single),
+        ("exit", "exit", Single),
+        ("confirm", "confirm", Single),
+        ("arm-after", "arm_after", Single),
+        ("checkpoint-every", "checkpoint_every", Multiple(2)),
+        ("tag", "tag", Single),
+        ("jobs", "jobs", Single),
+        ("seed", "seed", Single),
+        ("no-shell", "no_shell", Flag),
+        ("replace", "replace", Single),
+        ("shell", "shell", Single),
+    ]
+};
+
+/// Parse `--shell`: the shell and its arguments, to which the command is
+/// appended. A shell given on its own gets the flag it takes the command
+/// with.
+fn parse_shell(input: &str) -> Result<Vec<String>, String> {
+    let mut shell: Vec<String> = input.split_whitespace().map(str::to_owned).collect();
+    match shell.as_slice() {
+        [] => return Err("expected a shell, ex. bash".to_owned()),
+        [name] if name == "pwsh" || name == "powershell" => shell.push("-Command".to_owned()),
+        [_] => shell.push("-c".to_owned()),
+        _ => {}
+    }
+    Ok(shell)
+}
+
+/// Split `input` into words by the quoting rules of a POSIX shell: words
+/// are separated by whitespace, kept together by single or double quotes,
+/// and a backslash escapes the next character, except within single quotes.
+fn split_words(input: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_owned()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => { word.push('\\'); word.push(c); }
+                            None => return Err("unterminated double quote".to_owned()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_owned()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_owned()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
 fn precision_of(s: &str) -> usize {
     let after_point = match s.find('.') {
         // '.' is ASCII so has len 1
@@ -318,17 +2446,292 @@ fn precision_of(s: &str) -> usize {
     exp - after_point
 }
 
+/// `--arm-after`: a number of iterations or a duration.
+#[derive(Debug, Clone, Copy)]
+enum Warmup {
+    Iterations(usize),
+    Duration(Duration),
+}
+
+fn parse_warmup(input: &str) -> Result<Warmup, String> {
+    match input.parse::<usize>() {
+        Ok(iterations) => Ok(Warmup::Iterations(iterations)),
+        Err(_) => parse_duration(input).map(Warmup::Duration).map_err(|e| e.to_string()),
+    }
+}
+
 #[derive(Debug)]
 enum ErrorCode {
     Any,
     Code(u32),
 }
 
-fn get_error_code(input: &str) -> ErrorCode {
-    if let Ok(code) = input.parse::<u32>() {
-        ErrorCode::Code(code)
+fn get_error_code(input: &str) -> Result<ErrorCode, String> {
+    if input.is_empty() {
+        Ok(ErrorCode::Any)
+    } else {
+        input.parse::<u32>()
+            .map(ErrorCode::Code)
+            .map_err(|_| format!("'{}' is not an exit code", input))
+    }
+}
+
+/// The command line `args`, parsed as `cli`, completed by LOOP_* variables,
+/// then by the settings read from stdin and the `config` file.
+fn load_options(args: &[OsString], cli: &ArgMatches, config: Option<&Path>, stdin_settings: &[(String, config::Value)])
+    -> Result<Vec<OsString>, String>
+{
+    let mut settings = config::from_env(OPTIONS)?;
+    settings.extend_from_slice(stdin_settings);
+    settings.extend(config::load(config, false)?);
+    config::merge(OPTIONS, cli, args.to_vec(), &settings)
+}
+
+/// The options which a reload on SIGHUP applies.
+const RELOADED: &[&str] = &[
+    "every", "for-duration", "until-time", "utc", "until-contains", "until-match", "until-match-count",
+    "while-contains", "while-match", "ignore-case", "fixed-string", "until-changes", "until-same",
+    "until-success", "until-fail", "until-error", "checkpoint-every", "heartbeat-file", "warn-slower-than",
+];
+
+/// `--until-error`, with or without an exit code.
+fn until_error_of(until_error: &Option<Option<String>>) -> Result<Option<ErrorCode>, String> {
+    match until_error {
+        Some(code) => get_error_code(code.as_deref().unwrap_or("")).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The text conditions on the output, compiled with `--ignore-case` and
+/// `--fixed-string`.
+struct Patterns {
+    until_contains: Vec<Regex>,
+    until_match: Vec<Regex>,
+    while_contains: Option<Regex>,
+    while_match: Option<Regex>,
+}
+
+impl Patterns {
+    fn of(opt: &Opt) -> Result<Patterns, String> {
+        if opt.until_match_count.is_some() && opt.until_contains.is_empty() && opt.until_match.is_empty() {
+            return Err("--until-match-count requires --until-contains or --until-match".to_owned());
+        }
+        let compile = |option: &str, pattern: &str, literal: bool| {
+            let pattern = if literal { regex::escape(pattern) } else { pattern.to_owned() };
+            RegexBuilder::new(&pattern).case_insensitive(opt.ignore_case).build()
+                .map_err(|e| format!("Invalid {}: {}", option, e))
+        };
+        Ok(Patterns {
+            until_contains: opt.until_contains.iter()
+                .map(|string| compile("--until-contains", string, true))
+                .collect::<Result<_, _>>()?,
+            until_match: opt.until_match.iter()
+                .map(|regex| compile("--until-match", regex, opt.fixed_string))
+                .collect::<Result<_, _>>()?,
+            while_contains: opt.while_contains.as_ref()
+                .map(|string| compile("--while-contains", string, true))
+                .transpose()?,
+            while_match: opt.while_match.as_ref()
+                .map(|regex| compile("--while-match", regex, opt.fixed_string))
+                .transpose()?,
+        })
+    }
+}
+
+/// `--checkpoint-every` pairs of an interval and a command.
+fn parse_checkpoints(values: &[String]) -> Result<Vec<(usize, String)>, String> {
+    values.chunks(2)
+        .map(|checkpoint| match checkpoint[0].parse::<usize>() {
+            Ok(every) if every > 0 => Ok((every, checkpoint[1].clone())),
+            _ => Err(format!("Invalid checkpoint interval '{}'", checkpoint[0])),
+        })
+        .collect()
+}
+
+/// Parse the command line, reporting invalid arguments as a `LoopError`.
+fn parse_args(args: &[OsString]) -> ArgMatches<'static> {
+    match Opt::clap().get_matches_from_safe(args) {
+        Ok(matches) => matches,
+        Err(ref e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let json = error::json_requested(args);
+            let message = if json {
+                // Only the error itself, without the usage and colors.
+                let message = e.message.split("\n\nUSAGE").next().unwrap_or_default();
+                message.splitn(2, "error:").last().unwrap_or_default().trim_start_matches("\x1b[0m").trim().to_owned()
+            } else {
+                e.message
+            };
+            LoopError::Usage(message).exit(json)
+        }
+    }
+}
+
+/// Run the `--merge` command on the outputs kept in `dir`, concatenated on
+/// its stdin in the order of the iterations.
+fn merge_outputs(command: &str, dir: &Path) -> Result<ExitStatus, LoopError> {
+    let io_error = |e: io::Error| LoopError::Io(format!("Failed to merge outputs: {}", e));
+    let mut outputs: Vec<PathBuf> = fs::read_dir(dir).map_err(io_error)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    outputs.sort();
+    let mut merged = tempfile::tempfile().map_err(io_error)?;
+    for output in outputs {
+        io::copy(&mut fs::File::open(output).map_err(io_error)?, &mut merged).map_err(io_error)?;
+    }
+    merged.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    Exec::shell(command)
+        .stdin(Redirection::File(merged))
+        .env("LOOP_OUTPUT_DIR", dir)
+        .join()
+        .map_err(|e| LoopError::SpawnFailed(e.to_string()))
+}
+
+/// Keys or names with their values, in order.
+type Pairs = Vec<(String, String)>;
+
+/// `--extract-kv`: the `key=value` pairs in `output`, and the members of
+/// JSON objects on lines of their own. Values may be double-quoted; later
+/// pairs replace earlier ones of the same key.
+fn extract_kv(output: &str) -> Pairs {
+    let mut pairs: Pairs = Vec::new();
+    let mut add = |key: String, value: String| match pairs.iter_mut().find(|(k, _)| *k == key) {
+        Some(pair) => pair.1 = value,
+        None => pairs.push((key, value)),
+    };
+    for line in output.lines() {
+        if let Ok(Json::Object(members)) = Json::parse(line.trim()) {
+            for (key, value) in members {
+                add(key, value.to_text());
+            }
+            continue;
+        }
+        for word in line.split_whitespace() {
+            if let Some((key, value)) = word.split_once('=') {
+                let valid = !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c));
+                if valid {
+                    add(key.to_owned(), value.trim_matches('"').to_owned());
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// The delay asked for by a `Retry-After` header in `output`, in seconds
+/// or as an HTTP date.
+fn parse_retry_after(output: &str, now: SystemTime) -> Option<Duration> {
+    output.lines().rev()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if name.trim().eq_ignore_ascii_case("retry-after") { Some(value.trim()) } else { None }
+        })
+        .find_map(|value| match value.parse() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => parse_http_date(value).map(|date| date.duration_since(now).unwrap_or_default()),
+        })
+}
+
+/// Describe where `actual` output first differs from the `expected` one.
+fn first_difference(expected: &str, actual: &str) -> Option<String> {
+    let (mut expected_lines, mut actual_lines) = (expected.lines(), actual.lines());
+    for line in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(expected), Some(actual)) if expected == actual => {}
+            (Some(expected), Some(actual)) =>
+                return Some(format!("line {}: expected '{}', got '{}'", line, expected, actual)),
+            (Some(expected), None) => return Some(format!("line {}: expected '{}', got nothing", line, expected)),
+            (None, Some(actual)) => return Some(format!("line {}: expected nothing, got '{}'", line, actual)),
+            (None, None) => break,
+        }
+    }
+    None
+}
+
+/// The whole output of the last iteration.
+fn read_output(tmpfile: &mut fs::File) -> io::Result<String> {
+    let mut stdout = String::new();
+    tmpfile.seek(SeekFrom::Start(0))?;
+    tmpfile.read_to_string(&mut stdout)?;
+    Ok(stdout)
+}
+
+/// Parse a byte size with an optional binary unit, ex. 512, 10K, 1.5G.
+fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input.find(|c: char| c.is_alphabetic()).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let multiplier: u64 = match unit.to_uppercase().trim_end_matches("IB").trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("unknown size unit '{}'", unit)),
+    };
+    number.trim().parse::<f64>()
+        .map(|n| (n * multiplier as f64) as u64)
+        .map_err(|_| format!("invalid size '{}'", input))
+}
+
+/// Print a line of the command's output, on stderr with `--stdout-to-stderr`.
+fn relay(line: &str, to_stderr: bool) {
+    if to_stderr {
+        eprintln!("{}", line);
     } else {
-        ErrorCode::Any
+        println!("{}", line);
+    }
+}
+
+/// The name of an iteration in test reports, with its item if any.
+fn iteration_name(count: usize, item: Option<&str>) -> String {
+    match item {
+        Some(item) => format!("iteration {}: {}", count, item),
+        None => format!("iteration {}", count),
+    }
+}
+
+/// `--gha`: a GitHub Actions workflow command, ex. `::error::message`.
+fn workflow_command(name: &str, message: &str) -> String {
+    let message = message.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A");
+    format!("::{}::{}", name, message)
+}
+
+/// Print a warning on stderr, highlighted when stderr is a terminal.
+fn warn(message: &str) {
+    if atty::is(atty::Stream::Stderr) {
+        eprintln!("\x1b[1;33m{}\x1b[0m", message);
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+fn round_to_millis(duration: Duration) -> Duration {
+    Duration::from_millis(duration.as_secs() * 1000 + u64::from(duration.subsec_millis()))
+}
+
+/// Block until the `--sync-start` time has come or its rendezvous file
+/// has been created, or the loop is interrupted. The time is read like
+/// `--until-time`.
+fn wait_for_sync_start(sync_start: &str, utc: bool) {
+    if let Ok(start) = parse_until_time(sync_start) {
+        if let Ok(delay) = start.resolve(utc, SystemTime::now()).duration_since(SystemTime::now()) {
+            signal::sleep(delay);
+        }
+        return;
+    }
+    let rendezvous = Path::new(sync_start);
+    while !rendezvous.exists() && !signal::interrupted() {
+        signal::sleep(Duration::from_millis(5));
+    }
+}
+
+/// `--stdin-broadcast`: the contents of the file given as `@path`, or the
+/// string itself.
+fn read_payload(input: &str) -> Result<Vec<u8>, LoopError> {
+    match input.strip_prefix('@') {
+        Some(path) => fs::read(path).map_err(|e| LoopError::Io(format!("Failed to read {}: {}", path, e))),
+        None => Ok(input.as_bytes().to_vec()),
     }
 }
 
@@ -349,10 +2752,284 @@ struct Counter {
     step_by: f64,
 }
 
+/// `--until-dns`: a name to resolve, and the address it should resolve to.
+#[derive(Debug)]
+struct DnsName {
+    name: String,
+    expected: Option<IpAddr>,
+}
+
+impl DnsName {
+    fn resolved(&self) -> bool {
+        match (self.name.as_str(), 0).to_socket_addrs() {
+            Ok(mut addrs) => match self.expected {
+                Some(expected) => addrs.any(|addr| addr.ip() == expected),
+                None => addrs.next().is_some(),
+            },
+            Err(_) => false,
+        }
+    }
+}
+
+fn parse_dns_name(input: &str) -> Result<DnsName, String> {
+    let (name, expected) = match input.split_once('=') {
+        Some((name, expected)) => (name, Some(expected.parse().map_err(|_| format!("invalid address '{}'", expected))?)),
+        None => (input, None),
+    };
+    if name.is_empty() {
+        return Err(format!("expected NAME[=ADDRESS], got '{}'", input));
+    }
+    Ok(DnsName { name: name.to_owned(), expected })
+}
+
+/// `--batch`: the items of a batch of up to `n` exported as `$ITEMS`,
+/// joined by spaces, and one by one as `$ITEM1` to `$ITEM<n>`.
+fn batch_variables(batch: &[String], n: usize) -> Vec<(String, String)> {
+    let mut variables = vec![("ITEMS".to_owned(), batch.join(" "))];
+    for i in 0..n {
+        variables.push((format!("ITEM{}", i + 1), batch.get(i).cloned().unwrap_or_default()));
+    }
+    variables
+}
+
+fn parse_batch(input: &str) -> Result<usize, String> {
+    match input.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a number of items above 0, got '{}'", input)),
+    }
+}
+
+/// `--chaos`: the probability of a failure, from 0% to 100%.
+fn parse_chaos(input: &str) -> Result<f64, String> {
+    match parse_percent(input)? {
+        percent if (0.0..=100.0).contains(&percent) => Ok(percent),
+        _ => Err(format!("expected a probability from 0% to 100%, got '{}'", input)),
+    }
+}
+
+/// `--until-process`, `--while-process`: a regex matching whole process
+/// names, so a plain name matches exactly.
+fn parse_process_name(input: &str) -> Result<Regex, String> {
+    Regex::new(&format!("^(?:{})$", input)).map_err(|e| e.to_string())
+}
+
+/// `--for-range`: numbers generated as the loop gets to them.
+#[derive(Debug)]
+struct Range {
+    start: f64,
+    end: f64,
+    step: f64,
+    precision: usize,
+}
+
+impl Range {
+    fn len(&self) -> usize {
+        // allow for rounding errors of fractional steps
+        let steps = (self.end - self.start) / self.step + 1e-9;
+        if steps < 0.0 { 0 } else { steps as usize + 1 }
+    }
+
+    fn get(&self, index: usize) -> Option<String> {
+        if index < self.len() {
+            Some(format!("{:.*}", self.precision, self.start + self.step * index as f64))
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse `START..END[:STEP]`. The step defaults to 1, or -1 when counting
+/// down.
+fn parse_range(input: &str) -> Result<Range, String> {
+    let invalid = || format!("expected START..END[:STEP], ex. 1..1000:5, got '{}'", input);
+    let (bounds, step) = match input.rfind(':') {
+        Some(i) => (&input[..i], Some(&input[i + 1..])),
+        None => (input, None),
+    };
+    let dots = bounds.find("..").ok_or_else(invalid)?;
+    let (start, end) = (&bounds[..dots], &bounds[dots + 2..]);
+    let number = |s: &str| s.trim().parse::<f64>().map_err(|_| invalid());
+    let (start_value, end_value) = (number(start)?, number(end)?);
+    let step_value = match step {
+        Some(step) => number(step)?,
+        None if end_value < start_value => -1.0,
+        None => 1.0,
+    };
+    if step_value == 0.0 || !step_value.is_finite() {
+        return Err(format!("step of '{}' must not be zero", input));
+    }
+    let precision = precision_of(start.trim()).max(step.map_or(0, |step| precision_of(step.trim())));
+    Ok(Range { start: start_value, end: end_value, step: step_value, precision })
+}
+
+/// `--bisect`: the first failing value is searched for between `low` and
+/// `high`, the values below `low` succeeded and `high` failed, unless it is
+/// past the end.
+#[derive(Debug)]
+struct Bisect {
+    start: i64,
+    end: i64,
+    low: i64,
+    high: i64,
+}
+
+fn parse_bisect(input: &str) -> Result<Bisect, String> {
+    let invalid = || format!("expected LO..HI, ex. 1..1000, got '{}'", input);
+    let (start, end) = input.split_once("..").ok_or_else(invalid)?;
+    let start: i64 = start.trim().parse().map_err(|_| invalid())?;
+    let end: i64 = end.trim().parse().map_err(|_| invalid())?;
+    if start > end || end == i64::MAX {
+        return Err(invalid());
+    }
+    Ok(Bisect { start, end, low: start, high: end + 1 })
+}
+
+impl Bisect {
+    /// The value to test next, halving the range, if it is not found yet.
+    fn next(&self) -> Option<i64> {
+        if self.low < self.high {
+            Some(self.low + (self.high - self.low) / 2)
+        } else {
+            None
+        }
+    }
+
+    fn record(&mut self, success: bool) {
+        if let Some(value) = self.next() {
+            if success {
+                self.low = value + 1;
+            } else {
+                self.high = value;
+            }
+        }
+    }
+
+    fn print(&self) {
+        match (self.low < self.high, self.high > self.end) {
+            (false, false) => println!("First failure at {}", self.high),
+            (false, true) => println!("No failure in {}..{}", self.start, self.end),
+            (true, false) => println!("First failure in {}..{}", self.low, self.high),
+            (true, true) => println!("First failure in {}..{}, if any", self.low, self.end),
+        }
+    }
+}
+
+/// An iteration whose command was started and is not finished yet.
+struct Running {
+    count: usize,
+    /// `--jobs` slot, picking the CPU with `--pin-cpus`
+    slot: usize,
+    child: Popen,
+    tmpfile: fs::File,
+    /// `--discard-stderr`, `--match-stream`, `--expect-file`: the streams
+    /// of the command, told apart
+    streams: Option<Tee>,
+    run_start: Instant,
+    seed: Option<u64>,
+    tag: Option<String>,
+    sandbox: Option<Sandbox>,
+    lock: Option<FileLock>,
+    streamed: Option<Item>,
+    /// `--stop-on-first-success-per-item`: the item explored, and the value
+    /// of this iteration
+    key: Option<(String, String)>,
+    /// `--junit`: the item, naming the test case
+    item: Option<String>,
+}
+
+/// `--require-all`, `--require-any`: whether the stop conditions given hold
+/// after an iteration.
+#[derive(Debug)]
+struct Conditions {
+    all: bool,
+    given: usize,
+    held: usize,
+}
+
+impl Conditions {
+    fn new(all: bool) -> Conditions {
+        Conditions { all, given: 0, held: 0 }
+    }
+
+    fn check(&mut self, held: bool) {
+        self.given += 1;
+        if held {
+            self.held += 1;
+        }
+    }
+
+    fn met(&self) -> bool {
+        if self.all {
+            self.given > 0 && self.held == self.given
+        } else {
+            self.held > 0
+        }
+    }
+}
+
+/// `--stop-on-first-success-per-item`, `--budget`: the attempts at every
+/// item explored, and the first success of each.
+#[derive(Debug)]
+struct Sweep {
+    keys: usize,
+    /// Items in the order they were first tried, with their attempts and
+    /// the first successful value.
+    results: Vec<(String, u32, Option<String>)>,
+}
+
+impl Sweep {
+    fn new(keys: usize) -> Sweep {
+        Sweep { keys, results: Vec::new() }
+    }
+
+    fn succeeded(&self, key: &str) -> bool {
+        self.results.iter().any(|(k, _, success)| k == key && success.is_some())
+    }
+
+    fn all_succeeded(&self) -> bool {
+        self.results.iter().filter(|(_, _, success)| success.is_some()).count() >= self.keys
+    }
+
+    fn record(&mut self, key: String, item: String, success: bool) {
+        let index = match self.results.iter().position(|(k, _, _)| *k == key) {
+            Some(index) => index,
+            None => {
+                self.results.push((key, 0, None));
+                self.results.len() - 1
+            }
+        };
+        let result = &mut self.results[index];
+        if result.2.is_none() {
+            result.1 += 1;
+            if success {
+                result.2 = Some(item);
+            }
+        }
+    }
+
+    fn print(self) {
+        for (key, attempts, success) in self.results {
+            match success {
+                Some(item) => println!("{}:\tsucceeded with {} after {} attempt(s)", key, item, attempts),
+                None => println!("{}:\tno success in {} attempt(s)", key, attempts),
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Summary {
     successes: u32,
-    failures: Vec<u32>
+    failures: Vec<u32>,
+    failed_seeds: Option<Vec<u64>>,
+    slow_runs: Option<u32>,
+    dropped_items: Option<usize>,
+    /// `--extract-kv`: the pairs extracted from every iteration.
+    extracted: Option<Vec<(usize, Pairs)>>,
+    aggregate: Option<Aggregate>,
+    /// `--plot`: the durations of the iterations, unless --aggregate is
+    /// plotted.
+    durations: Option<Vec<Duration>>,
 }
 
 impl Summary {
@@ -362,8 +3039,8 @@ impl Summary {
         let errors = if self.failures.is_empty() {
             String::from("0")
         } else {
-            format!("{} ({})", self.failures.len(), self.failures.as_slice().into_iter()
-                    .map(|f| ((*f as u32)).to_string())
+            format!("{} ({})", self.failures.len(), self.failures.iter()
+                    .map(|f| f.to_string())
                     .collect::<Vec<String>>()
                     .join(", "))
         };
@@ -371,6 +3048,65 @@ impl Summary {
         println!("Total runs:\t{}", total);
         println!("Successes:\t{}", self.successes);
         println!("Failures:\t{}", errors);
+        if let Some(failed_seeds) = self.failed_seeds {
+            if !failed_seeds.is_empty() {
+                println!("Failed seeds:\t{}", failed_seeds.iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", "));
+            }
+        }
+        if let Some(slow_runs) = self.slow_runs {
+            println!("Slow runs:\t{}", slow_runs);
+        }
+        if let Some(dropped_items) = self.dropped_items {
+            println!("Dropped items:\t{}", dropped_items);
+        }
+        if let Some(ref aggregate) = self.aggregate {
+            for (name, value) in aggregate.report() {
+                println!("{}:\t{}", name, value);
+            }
+        }
+        // --plot
+        if let Some(durations) = self.durations {
+            let (values, min, max) = match self.aggregate {
+                Some(ref aggregate) => {
+                    let values = aggregate.values().to_vec();
+                    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+                    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                    (values, stats::format_number(min), stats::format_number(max))
+                }
+                None => {
+                    let format = |duration: Option<&Duration>| {
+                        format_duration(round_to_millis(duration.cloned().unwrap_or_default())).to_string()
+                    };
+                    let values = durations.iter().map(Duration::as_secs_f64).collect();
+                    (values, format(durations.iter().min()), format(durations.iter().max()))
+                }
+            };
+            if !values.is_empty() {
+                println!("Plot:\t{} {}..{}", stats::sparkline(&values, PLOT_WIDTH), min, max);
+            }
+        }
+        // A table with a column per key, in the order they were first seen
+        if let Some(extracted) = self.extracted {
+            let mut keys: Vec<&str> = Vec::new();
+            for (key, _) in extracted.iter().flat_map(|(_, pairs)| pairs) {
+                if !keys.contains(&key.as_str()) {
+                    keys.push(key);
+                }
+            }
+            if !keys.is_empty() {
+                println!();
+                println!("Iteration\t{}", keys.join("\t"));
+                for (count, pairs) in &extracted {
+                    let values: Vec<&str> = keys.iter()
+                        .map(|key| pairs.iter().find(|(k, _)| k == key).map_or("", |(_, value)| value.as_str()))
+                        .collect();
+                    println!("{}\t{}", count, values.join("\t"));
+                }
+            }
+        }
     }
 }
 
@@ -0,0 +1,148 @@
+//! A minimal MQTT 3.1.1 subscriber for `--mqtt-trigger` and `--until-mqtt`.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use items::{Item, Queue};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:1883";
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+
+/// Whether `topic` matches the topic filter `filter`, with its `+` and `#`
+/// wildcards.
+fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for pattern in filter.split('/') {
+        match (pattern, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (pattern, Some(level)) if pattern == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+fn encode_string(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+fn write_packet(stream: &mut TcpStream, kind: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![kind];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        packet.push(if length > 0 { byte | 0x80 } else { byte });
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    stream.write_all(&packet)
+}
+
+/// Read a packet, returning its type and flags, and its body.
+fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0];
+    stream.read_exact(&mut byte)?;
+    let kind = byte[0];
+    let (mut length, mut multiplier) = (0, 1);
+    loop {
+        stream.read_exact(&mut byte)?;
+        length += (byte[0] & 0x7f) as usize * multiplier;
+        multiplier *= 128;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok((kind, body))
+}
+
+/// Split a PUBLISH body into its topic and payload.
+fn parse_publish(flags: u8, body: &[u8]) -> Option<(String, String)> {
+    let length = u16::from_be_bytes([*body.first()?, *body.get(1)?]) as usize;
+    let topic = body.get(2..2 + length)?;
+    // QoS 1 and 2 carry a packet identifier
+    let payload = body.get(2 + length + if flags & 0x06 != 0 { 2 } else { 0 }..)?;
+    Some((String::from_utf8_lossy(topic).into_owned(), String::from_utf8_lossy(payload).into_owned()))
+}
+
+/// Connect to the broker at `address` and subscribe from a thread: messages
+/// on the `trigger` topic are pushed into its queue as items, and the
+/// returned flag is set once one of the `until` topics received its payload.
+pub fn subscribe(address: &str, trigger: Option<(String, Arc<Queue>)>, until: Vec<(String, String)>)
+    -> io::Result<Arc<AtomicBool>>
+{
+    let mut stream = TcpStream::connect(address)?;
+    let mut connect = Vec::new();
+    encode_string(&mut connect, "MQTT");
+    // protocol level 4, clean session, no keep alive
+    connect.extend_from_slice(&[4, 0x02, 0, 0]);
+    encode_string(&mut connect, &format!("loop-{}", process::id()));
+    write_packet(&mut stream, CONNECT, &connect)?;
+    match read_packet(&mut stream)? {
+        (CONNACK, ref body) if body.get(1) == Some(&0) => {}
+        (CONNACK, body) => return Err(io::Error::other(format!("connection refused with code {}", body.get(1).unwrap_or(&0)))),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected CONNACK")),
+    }
+
+    let mut subscribe = vec![0, 1];
+    for topic in trigger.iter().map(|trigger| &trigger.0).chain(until.iter().map(|until| &until.0)) {
+        encode_string(&mut subscribe, topic);
+        subscribe.push(0);
+    }
+    write_packet(&mut stream, SUBSCRIBE, &subscribe)?;
+
+    let matched = Arc::new(AtomicBool::new(false));
+    let until_matched = matched.clone();
+    thread::spawn(move || {
+        loop {
+            let (kind, body) = match read_packet(&mut stream) {
+                Ok(packet) => packet,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    eprintln!("MQTT connection failed: {}", e);
+                    break;
+                }
+            };
+            match kind & 0xf0 {
+                SUBACK if body.iter().skip(2).any(|&code| code == 0x80) => {
+                    eprintln!("MQTT broker refused the subscription");
+                    break;
+                }
+                PUBLISH => {
+                    let (topic, payload) = match parse_publish(kind & 0x0f, &body) {
+                        Some(message) => message,
+                        None => continue,
+                    };
+                    if let Some((ref filter, ref queue)) = trigger {
+                        if topic_matches(filter, &topic) {
+                            queue.push(Item::new(payload.clone()));
+                        }
+                    }
+                    if until.iter().any(|(filter, expected)| topic_matches(filter, &topic) && payload == *expected) {
+                        until_matched.store(true, Ordering::SeqCst);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some((_, queue)) = trigger {
+            queue.close();
+        }
+    });
+    Ok(matched)
+}
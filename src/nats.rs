@@ -0,0 +1,105 @@
+//! A minimal NATS subscriber feeding `--consume`.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use items::{Item, Queue, Receipt};
+
+const DEFAULT_PORT: u16 = 4222;
+
+/// Where to consume from: `nats://host[:port]/subject`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub address: String,
+    pub subject: String,
+}
+
+pub fn parse_source(input: &str) -> Result<Source, String> {
+    let rest = match input.find("://") {
+        Some(i) if &input[..i] == "nats" => &input[i + 3..],
+        Some(i) => return Err(format!("unsupported scheme '{}', only nats:// is supported", &input[..i])),
+        None => return Err(format!("expected nats://host[:port]/subject, got '{}'", input)),
+    };
+    let slash = rest.find('/').ok_or_else(|| format!("missing subject in '{}'", input))?;
+    let (host, subject) = (&rest[..slash], &rest[slash + 1..]);
+    if host.is_empty() || subject.is_empty() || subject.contains(char::is_whitespace) {
+        return Err(format!("expected nats://host[:port]/subject, got '{}'", input));
+    }
+    let address = if host.contains(':') { host.to_owned() } else { format!("{}:{}", host, DEFAULT_PORT) };
+    Ok(Source { address, subject: subject.to_owned() })
+}
+
+/// Acknowledges a message to its reply subject, as JetStream expects:
+/// `+ACK` after a successful iteration, `-NAK` to have it redelivered.
+struct Acknowledgement {
+    connection: Arc<Mutex<TcpStream>>,
+    reply: String,
+}
+
+impl Receipt for Acknowledgement {
+    fn settle(self: Box<Self>, success: bool) {
+        let ack = if success { "+ACK" } else { "-NAK" };
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = write!(connection, "PUB {} {}\r\n{}\r\n", self.reply, ack.len(), ack);
+    }
+}
+
+/// Subscribe to `source`, pushing every message into `queue` from a thread.
+/// With `ack`, messages carrying a reply subject are acknowledged once
+/// their iteration finished.
+pub fn consume(source: &Source, queue: Arc<Queue>, ack: bool) -> io::Result<()> {
+    let stream = TcpStream::connect(&source.address)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut info = String::new();
+    reader.read_line(&mut info)?;
+    if !info.starts_with("INFO") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected greeting '{}'", info.trim())));
+    }
+    let connection = Arc::new(Mutex::new(stream));
+    write!(connection.lock().unwrap_or_else(|e| e.into_inner()),
+           "CONNECT {{\"verbose\":false,\"pedantic\":false,\"name\":\"loop\"}}\r\nSUB {} 1\r\n", source.subject)?;
+
+    thread::spawn(move || {
+        if let Err(e) = receive(&mut reader, &connection, &queue, ack) {
+            eprintln!("NATS connection failed: {}", e);
+        }
+        queue.close();
+    });
+    Ok(())
+}
+
+fn receive(reader: &mut BufReader<TcpStream>, connection: &Arc<Mutex<TcpStream>>, queue: &Queue, ack: bool)
+    -> io::Result<()>
+{
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().cloned() {
+            Some("PING") => connection.lock().unwrap_or_else(|e| e.into_inner()).write_all(b"PONG\r\n")?,
+            Some("-ERR") => return Err(io::Error::other(line.trim().to_owned())),
+            // MSG <subject> <sid> [reply-to] <#bytes>
+            Some("MSG") if words.len() == 4 || words.len() == 5 => {
+                let size: usize = words[words.len() - 1].parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, line.trim().to_owned()))?;
+                let mut payload = vec![0; size + 2];
+                reader.read_exact(&mut payload)?;
+                payload.truncate(size);
+                let mut item = Item::new(String::from_utf8_lossy(&payload).into_owned());
+                if ack && words.len() == 5 {
+                    item.receipt = Some(Box::new(Acknowledgement {
+                        connection: connection.clone(),
+                        reply: words[3].to_owned(),
+                    }));
+                }
+                queue.push(item);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Reachability of a host for `--until-ping` and `--while-ping`.
+//!
+//! Hosts are sent an ICMP echo request over an unprivileged ping socket.
+//! Where those are not permitted (see `net.ipv4.ping_group_range`), or a
+//! port is given, a TCP connection is attempted instead: a refused one
+//! still proves the host is up.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::process;
+use std::time::Duration;
+
+use libc;
+
+/// How long a probe waits for an answer.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Port probed over TCP when ICMP is not available.
+const DEFAULT_PORT: u16 = 22;
+
+const ECHO_REQUEST: u8 = 8;
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// A host to probe, ex. `db1`, or `db1:5432` to probe over TCP only.
+#[derive(Debug)]
+pub struct Host {
+    name: String,
+    port: Option<u16>,
+}
+
+pub fn parse_host(input: &str) -> Result<Host, String> {
+    let (name, port) = match input.rsplit_once(':') {
+        // IPv6 addresses are bracketed when a port is given
+        Some((name, port)) if !name.contains(':') || name.ends_with(']') => {
+            let port = port.parse().map_err(|_| format!("invalid port '{}'", port))?;
+            (name.trim_start_matches('[').trim_end_matches(']'), Some(port))
+        }
+        _ => (input, None),
+    };
+    if name.is_empty() {
+        return Err(format!("expected HOST[:PORT], got '{}'", input));
+    }
+    Ok(Host { name: name.to_owned(), port })
+}
+
+impl Host {
+    /// Whether the host answers a probe within a second.
+    pub fn reachable(&self) -> bool {
+        let addrs = match (self.name.as_str(), self.port.unwrap_or(DEFAULT_PORT)).to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(_) => return false,
+        };
+        addrs.into_iter().any(|addr| {
+            if self.port.is_none() {
+                match echo(addr.ip()) {
+                    Ok(replied) => return replied,
+                    Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+                    Err(_) => return false,
+                }
+            }
+            connect(addr)
+        })
+    }
+}
+
+/// Send an ICMP echo request to `ip` and wait for the reply. Fails with
+/// `PermissionDenied` if ping sockets are not permitted.
+fn echo(ip: IpAddr) -> io::Result<bool> {
+    let (domain, protocol, request, reply) = match ip {
+        IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP, ECHO_REQUEST, ECHO_REPLY),
+        IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6, ECHO_REQUEST_V6, ECHO_REPLY_V6),
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SOCK_CLOEXEC is not portable, ex. to macOS.
+    unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    // A ping socket is a datagram socket exchanging ICMP messages, whose
+    // identifier and checksum are filled in by the kernel.
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(SocketAddr::new(ip, 0))?;
+
+    let sequence = (process::id() as u16).to_be_bytes();
+    let request = [request, 0, 0, 0, 0, 0, sequence[0], sequence[1]];
+    if socket.send(&request).is_err() {
+        return Ok(false);
+    }
+    let mut buffer = [0; 64];
+    loop {
+        match socket.recv(&mut buffer) {
+            Ok(n) if n >= 8 && buffer[0] == reply && buffer[6..8] == sequence => return Ok(true),
+            Ok(_) => {}
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// Whether a TCP connection to `addr` is accepted or refused.
+fn connect(addr: SocketAddr) -> bool {
+    match TcpStream::connect_timeout(&addr, TIMEOUT) {
+        Ok(_) => true,
+        Err(e) => e.kind() == io::ErrorKind::ConnectionRefused,
+    }
+}
@@ -0,0 +1,117 @@
+//! Process information from `/proc` (Linux).
+
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use libc;
+use regex::Regex;
+
+/// Whether `/proc` can be read for processes on this platform, which the
+/// options built on it require.
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+fn pids() -> Vec<u32> {
+    match fs::read_dir("/proc") {
+        Ok(entries) => entries.filter_map(Result::ok)
+            .filter_map(|e| e.file_name().to_str().and_then(|n| n.parse::<u32>().ok()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// `pid` followed by all of its descendants, which are only known on
+/// Linux. Elsewhere it is `pid` alone.
+pub fn process_tree(pid: u32) -> Vec<u32> {
+    let parents: Vec<(u32, u32)> = pids().into_iter()
+        .filter_map(|p| parent_of(p).map(|parent| (p, parent)))
+        .collect();
+
+    let mut tree = vec![pid];
+    let mut i = 0;
+    while i < tree.len() {
+        let current = tree[i];
+        tree.extend(parents.iter().filter(|&&(_, parent)| parent == current).map(|&(p, _)| p));
+        i += 1;
+    }
+    tree
+}
+
+/// The fields of `/proc/<pid>/stat` following the command name.
+fn stat_fields(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is parenthesized and may itself contain spaces.
+    let rest = &stat[stat.rfind(')')? + 1..];
+    Some(rest.split_whitespace().map(String::from).collect())
+}
+
+fn parent_of(pid: u32) -> Option<u32> {
+    stat_fields(pid)?.get(1)?.parse().ok()
+}
+
+/// Resident set size of a single process in bytes.
+pub fn resident_memory(pid: u32) -> Option<u64> {
+    let statm = fs::read_to_string(format!("/proc/{}/statm", pid)).ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * page_size())
+}
+
+/// Combined resident set size of `pid` and its descendants in bytes.
+pub fn tree_resident_memory(pid: u32) -> u64 {
+    process_tree(pid).into_iter().filter_map(resident_memory).sum()
+}
+
+/// CPU time used by a process and its reaped children.
+pub fn cpu_time(pid: u32) -> Option<Duration> {
+    let fields = stat_fields(pid)?;
+    // utime, stime, cutime and cstime, in clock ticks
+    let ticks: u64 = fields.get(11..15)?.iter()
+        .filter_map(|t| t.parse::<u64>().ok())
+        .sum();
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+    Some(Duration::from_millis(ticks * 1000 / ticks_per_second))
+}
+
+/// Combined CPU time of `pid` and its descendants.
+pub fn tree_cpu_time(pid: u32) -> Duration {
+    process_tree(pid).into_iter().filter_map(cpu_time).sum()
+}
+
+/// The names of a process: the command name, which the kernel truncates to
+/// 15 bytes, and the file name of the program it was started as.
+fn names(pid: u32) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Ok(comm) = fs::read_to_string(format!("/proc/{}/comm", pid)) {
+        names.push(comm.trim_end_matches('\n').to_owned());
+    }
+    if let Ok(cmdline) = fs::read(format!("/proc/{}/cmdline", pid)) {
+        let program = String::from_utf8_lossy(cmdline.split(|&b| b == 0).next().unwrap_or_default()).into_owned();
+        if let Some(name) = Path::new(&program).file_name() {
+            names.push(name.to_string_lossy().into_owned());
+        }
+    }
+    names
+}
+
+/// Whether a process other than this one and its descendants has a name
+/// matching `pattern`.
+pub fn process_running(pattern: &Regex) -> bool {
+    let own = process_tree(process::id());
+    pids().into_iter()
+        .filter(|pid| !own.contains(pid))
+        .any(|pid| names(pid).iter().any(|name| pattern.is_match(name)))
+}
+
+/// Send `signal` to `pid` and, on Linux, all of its descendants.
+pub fn signal_tree(pid: u32, signal: libc::c_int) {
+    for p in process_tree(pid) {
+        unsafe {
+            libc::kill(p as libc::pid_t, signal);
+        }
+    }
+}
+
+fn page_size() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
@@ -0,0 +1,146 @@
+//! A minimal Redis client popping the items of `--for-redis` off a list.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use items::{Item, Queue, Receipt};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:6379";
+
+/// What to take items from: `list:<key>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub key: String,
+}
+
+pub fn parse_source(input: &str) -> Result<Source, String> {
+    match input.strip_prefix("list:") {
+        Some(key) if !key.is_empty() => Ok(Source { key: key.to_owned() }),
+        _ => Err(format!("expected list:<key>, got '{}'", input)),
+    }
+}
+
+/// A reply of the server, as far as the loop needs it.
+enum Reply {
+    Nil,
+    Status,
+    Integer,
+    Bulk(Vec<u8>),
+    Array(Vec<Reply>),
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(address: &str) -> io::Result<Connection> {
+        let writer = TcpStream::connect(address)?;
+        Ok(Connection { reader: BufReader::new(writer.try_clone()?), writer })
+    }
+
+    /// Send a command and wait for its reply.
+    fn call(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        let mut command = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            command.extend(format!("${}\r\n", arg.len()).bytes());
+            command.extend_from_slice(arg);
+            command.extend(b"\r\n");
+        }
+        self.writer.write_all(&command)?;
+        self.read_reply()
+    }
+
+    fn read_reply(&mut self) -> io::Result<Reply> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply '{}'", line));
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Err(invalid(line));
+        }
+        let (kind, rest) = line.split_at(1);
+        let length = || rest.parse::<i64>().map_err(|_| invalid(line));
+        match kind {
+            "+" => Ok(Reply::Status),
+            "-" => Err(io::Error::other(rest.to_owned())),
+            ":" => length().map(|_| Reply::Integer),
+            "$" => match length()? {
+                length if length < 0 => Ok(Reply::Nil),
+                length => {
+                    let mut data = vec![0; length as usize + 2];
+                    self.reader.read_exact(&mut data)?;
+                    data.truncate(length as usize);
+                    Ok(Reply::Bulk(data))
+                }
+            },
+            "*" => match length()? {
+                length if length < 0 => Ok(Reply::Nil),
+                length => (0..length).map(|_| self.read_reply()).collect::<io::Result<_>>().map(Reply::Array),
+            },
+            _ => Err(invalid(line)),
+        }
+    }
+}
+
+/// Pushes the item of a failed iteration to the `--dead-letter` list.
+struct DeadLetter {
+    connection: Arc<Mutex<Connection>>,
+    key: String,
+    value: String,
+}
+
+impl Receipt for DeadLetter {
+    fn settle(self: Box<Self>, success: bool) {
+        if success {
+            return;
+        }
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = connection.call(&[b"LPUSH", self.key.as_bytes(), self.value.as_bytes()]) {
+            eprintln!("Failed to push '{}' to {}: {}", self.value, self.key, e);
+        }
+    }
+}
+
+/// Pop items off the list of `source` into `queue` from a thread, blocking
+/// while the list is empty. Items of failed iterations are pushed to the
+/// `dead_letter` list, if given.
+pub fn consume(address: &str, source: &Source, dead_letter: Option<String>, queue: Arc<Queue>) -> io::Result<()> {
+    let mut connection = Connection::open(address)?;
+    let dead_letter = match dead_letter {
+        Some(key) => Some((key, Arc::new(Mutex::new(Connection::open(address)?)))),
+        None => None,
+    };
+    let key = source.key.clone();
+
+    thread::spawn(move || {
+        loop {
+            match connection.call(&[b"BRPOP", key.as_bytes(), b"0"]) {
+                Ok(Reply::Array(ref reply)) if reply.len() == 2 => {
+                    let value = match reply[1] {
+                        Reply::Bulk(ref value) => String::from_utf8_lossy(value).into_owned(),
+                        _ => continue,
+                    };
+                    let mut item = Item::new(value.clone());
+                    if let Some((ref key, ref connection)) = dead_letter {
+                        item.receipt = Some(Box::new(DeadLetter { connection: connection.clone(), key: key.clone(), value }));
+                    }
+                    queue.push(item);
+                }
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    eprintln!("Redis connection failed: {}", e);
+                    break;
+                }
+            }
+        }
+        queue.close();
+    });
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! `--report`: a JSON file written however the loop ended, for CI jobs to
+//! archive.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime};
+
+use humantime::format_rfc3339_seconds;
+
+use json::Json;
+
+/// Lines of the last output kept in the report.
+const EXCERPT_LINES: usize = 20;
+
+#[derive(Debug)]
+pub struct Report {
+    path: PathBuf,
+    /// SHA-256 of the resolved options, telling runs of the same
+    /// configuration apart from others
+    config_hash: String,
+    started: SystemTime,
+    start: Instant,
+    iterations: u64,
+    successes: u64,
+}
+
+/// How the loop ended.
+pub struct Ending<'a> {
+    pub reason: &'a str,
+    pub error: Option<String>,
+    pub exit_code: i32,
+    pub last_output: &'a str,
+}
+
+impl Report {
+    pub fn new(path: PathBuf, config_hash: String) -> Report {
+        Report { path, config_hash, started: SystemTime::now(), start: Instant::now(), iterations: 0, successes: 0 }
+    }
+
+    pub fn record(&mut self, success: bool) {
+        self.iterations += 1;
+        if success {
+            self.successes += 1;
+        }
+    }
+
+    pub fn write(&self, ending: &Ending) -> io::Result<()> {
+        let lines: Vec<&str> = ending.last_output.lines().collect();
+        let excerpt = lines[lines.len().saturating_sub(EXCERPT_LINES)..].join("\n");
+        let number = |n: u64| Json::Number(n.to_string());
+        let report = Json::Object(vec![
+            ("config_sha256".to_owned(), Json::String(self.config_hash.clone())),
+            ("started".to_owned(), Json::String(format_rfc3339_seconds(self.started).to_string())),
+            ("ended".to_owned(), Json::String(format_rfc3339_seconds(SystemTime::now()).to_string())),
+            ("duration_secs".to_owned(), Json::Number(format!("{:.3}", self.start.elapsed().as_secs_f64()))),
+            ("iterations".to_owned(), number(self.iterations)),
+            ("successes".to_owned(), number(self.successes)),
+            ("failures".to_owned(), number(self.iterations - self.successes)),
+            ("reason".to_owned(), Json::String(ending.reason.to_owned())),
+            ("error".to_owned(), ending.error.clone().map_or(Json::Null, Json::String)),
+            ("exit_code".to_owned(), Json::Number(ending.exit_code.to_string())),
+            ("last_output".to_owned(), Json::String(excerpt)),
+        ]);
+        fs::write(&self.path, format!("{}\n", report))
+    }
+}
@@ -0,0 +1,115 @@
+//! Keeping to the `--every` schedule across system suspend.
+//!
+//! The loop measures time with the monotonic clock, which stands still while
+//! the system is suspended. The boot time clock keeps counting, so the
+//! difference between the two is the time spent suspended. On macOS, the
+//! uptime clock stands still and the monotonic clock keeps counting. Other
+//! platforms don't support `--after-resume`.
+
+use std::mem;
+use std::time::Duration;
+
+use humantime::format_duration;
+use libc;
+
+use signal;
+
+/// Time suspended between two iterations before the loop considers the
+/// system to have been asleep.
+const MIN_SUSPEND: Duration = Duration::from_secs(1);
+
+/// How often waits check whether the system was suspended.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The clock standing still while the system is suspended, and the one
+/// counting on.
+#[cfg(target_os = "linux")]
+const CLOCKS: (libc::clockid_t, libc::clockid_t) = (libc::CLOCK_MONOTONIC, libc::CLOCK_BOOTTIME);
+#[cfg(target_os = "macos")]
+const CLOCKS: (libc::clockid_t, libc::clockid_t) = (CLOCK_UPTIME_RAW, libc::CLOCK_MONOTONIC);
+
+/// `CLOCK_UPTIME_RAW` of `<time.h>`, missing from the libc crate.
+#[cfg(target_os = "macos")]
+const CLOCK_UPTIME_RAW: libc::clockid_t = 8;
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const CLOCKS: (libc::clockid_t, libc::clockid_t) = (libc::CLOCK_MONOTONIC, libc::CLOCK_MONOTONIC);
+
+/// Whether suspends can be told on this platform.
+const SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "macos"));
+
+/// `--after-resume`: what to do with the iterations missed while the
+/// system was suspended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AfterResume {
+    /// Run all missed iterations right away.
+    Run,
+    /// Skip them, waiting for the next iteration time of the schedule.
+    Skip,
+    /// Run one iteration right away, and schedule the next ones from it.
+    Reset,
+}
+
+pub fn parse_after_resume(input: &str) -> Result<AfterResume, String> {
+    if !SUPPORTED {
+        return Err("--after-resume is only supported on Linux and macOS".to_owned());
+    }
+    match input {
+        "run" => Ok(AfterResume::Run),
+        "skip" => Ok(AfterResume::Skip),
+        "reset" => Ok(AfterResume::Reset),
+        _ => Err(format!("expected run, skip or reset, got '{}'", input)),
+    }
+}
+
+fn clock(id: libc::clockid_t) -> Duration {
+    unsafe {
+        let mut time: libc::timespec = mem::zeroed();
+        libc::clock_gettime(id, &mut time);
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    }
+}
+
+/// A point in time of the schedule, counting the time spent suspended.
+#[derive(Debug, Clone, Copy)]
+pub struct Mark {
+    since_boot: Duration,
+    suspended: Duration,
+}
+
+impl Mark {
+    pub fn now() -> Mark {
+        let (awake, since_boot) = (clock(CLOCKS.0), clock(CLOCKS.1));
+        Mark { since_boot, suspended: since_boot.saturating_sub(awake) }
+    }
+}
+
+/// Wait until `every` after the iteration started at `start`, handling a
+/// suspend of the system on the way according to `after_resume`. Returns
+/// the number of further missed iterations to run right away.
+pub fn wait(every: Duration, start: Mark, after_resume: AfterResume) -> usize {
+    let mut due = start.since_boot + every;
+    let mut suspended = start.suspended;
+    while !signal::interrupted() {
+        let now = Mark::now();
+        if now.suspended >= suspended + MIN_SUSPEND {
+            let elapsed = now.since_boot - start.since_boot;
+            let missed = (elapsed.as_nanos() / every.as_nanos().max(1)) as usize;
+            suspended = now.suspended;
+            if missed > 0 {
+                eprintln!("Resumed after {} suspended, {} iteration(s) missed",
+                          format_duration(Duration::from_secs((now.suspended - start.suspended).as_secs())),
+                          missed);
+                match after_resume {
+                    AfterResume::Run => return missed - 1,
+                    AfterResume::Reset => return 0,
+                    AfterResume::Skip => due = start.since_boot + every * (missed as u32 + 1),
+                }
+            }
+        }
+        match due.checked_sub(now.since_boot) {
+            Some(left) if left > Duration::from_secs(0) => signal::sleep(left.min(POLL_INTERVAL)),
+            _ => break,
+        }
+    }
+    0
+}
@@ -0,0 +1,50 @@
+//! Fresh per-iteration working directories for `--sandbox-dir`.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use tempfile::{self, TempDir};
+
+pub struct Sandbox {
+    dir: TempDir,
+}
+
+impl Sandbox {
+    /// Create a temporary directory populated with a copy of `template`.
+    pub fn new(template: &Path) -> io::Result<Sandbox> {
+        let dir = tempfile::Builder::new().prefix("loop-sandbox-").tempdir()?;
+        copy_dir(template, dir.path())?;
+        Ok(Sandbox { dir })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Keep the directory around instead of deleting it on drop.
+    pub fn keep(self) -> PathBuf {
+        self.dir.into_path()
+    }
+}
+
+/// Copy the contents of `from` into `to`. Symlinks are recreated as they
+/// are rather than followed, so that cycles and dangling links are copied
+/// too.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_dir() {
+            fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! CPU affinity and priority of the command's processes. Affinity is only
+//! supported on Linux.
+
+use std::io;
+#[cfg(target_os = "linux")]
+use std::mem;
+
+use libc;
+
+/// Whether `--pin-cpus` is available on this platform.
+pub const PINNING_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// The CPUs the loop may run on.
+#[cfg(target_os = "linux")]
+pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+    let set = affinity()?;
+    Ok((0..libc::CPU_SETSIZE as usize).filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) }).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+    Err(unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unsupported() -> io::Error {
+    io::Error::other("CPU affinity is only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+fn affinity() -> io::Result<libc::cpu_set_t> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(set: &libc::cpu_set_t) -> io::Result<()> {
+    if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), set) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Restricts the loop to a single CPU until dropped, so that processes
+/// started meanwhile inherit the restriction.
+pub struct Pinned {
+    #[cfg(target_os = "linux")]
+    previous: libc::cpu_set_t,
+}
+
+#[cfg(target_os = "linux")]
+impl Pinned {
+    pub fn to(cpu: usize) -> io::Result<Pinned> {
+        let previous = affinity()?;
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+        set_affinity(&set)?;
+        Ok(Pinned { previous })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Pinned {
+    pub fn to(_cpu: usize) -> io::Result<Pinned> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Pinned {
+    fn drop(&mut self) {
+        let _ = set_affinity(&self.previous);
+    }
+}
+
+/// The location of `errno` of the calling thread.
+#[cfg(any(target_os = "linux", target_os = "emscripten", target_os = "redox"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+/// Lower the priority of the loop, and thereby of every command it starts.
+pub fn renice(increment: i32) -> io::Result<()> {
+    // -1 is a valid niceness, so a failure only shows in errno, which is
+    // cleared first. nice(2) only fails for lack of the permission to
+    // raise the priority.
+    let niceness = unsafe {
+        *errno() = 0;
+        libc::nice(increment)
+    };
+    if niceness == -1 {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(0) {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
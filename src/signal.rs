@@ -0,0 +1,67 @@
+//! Signals handled by the loop itself.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+/// How often sleeps check whether the loop was interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_hangup(_: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+/// Set `handler` for `signal`. System calls are restarted after it ran, as
+/// waiting for the command is not prepared for `EINTR`, so any other wait
+/// has to poll `interrupted()`, ex. with `sleep`.
+fn handle(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+}
+
+/// Handle SIGINT by finishing the current iteration instead of dying with
+/// it, so the loop can still report its results.
+pub fn install() {
+    handle(libc::SIGINT, on_interrupt);
+}
+
+/// Handle SIGHUP by requesting a reload instead of terminating.
+pub fn install_hangup() {
+    handle(libc::SIGHUP, on_hangup);
+}
+
+/// Whether SIGHUP was received since the last call.
+pub fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Sleep for `duration`, waking early when interrupted.
+pub fn sleep(duration: Duration) {
+    let start = Instant::now();
+    while !interrupted() {
+        match duration.checked_sub(start.elapsed()) {
+            Some(left) => thread::sleep(left.min(POLL_INTERVAL)),
+            None => break,
+        }
+    }
+}
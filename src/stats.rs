@@ -0,0 +1,354 @@
+//! Rolling statistics over the iterations of a loop.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use humantime::{format_duration, parse_duration};
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Metric {
+    Duration,
+    FailureRate,
+}
+
+/// `--until-trend`: the mean of a metric over the latest window compared
+/// with the mean over the first window of the loop.
+#[derive(Debug)]
+pub struct Trend {
+    metric: Metric,
+    change: f64,
+    window: usize,
+    baseline: Vec<f64>,
+    recent: VecDeque<f64>,
+}
+
+impl Trend {
+    /// Record an iteration, returning a description of the degradation
+    /// once the trend condition is met.
+    pub fn record(&mut self, duration: Duration, success: bool) -> Option<String> {
+        let value = match self.metric {
+            Metric::Duration => duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9,
+            Metric::FailureRate => if success { 0.0 } else { 1.0 },
+        };
+        if self.baseline.len() < self.window {
+            self.baseline.push(value);
+            return None;
+        }
+        self.recent.push_back(value);
+        if self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < self.window {
+            return None;
+        }
+
+        let before = mean(self.baseline.iter().cloned());
+        let now = mean(self.recent.iter().cloned());
+        match self.metric {
+            // Relative change of the mean duration.
+            Metric::Duration if before > 0.0 && (now - before) / before * 100.0 >= self.change => Some(
+                format!("mean duration went from {:.3}s to {:.3}s over {} iterations", before, now, self.window)),
+            // Failure rates change in percentage points.
+            Metric::FailureRate if (now - before) * 100.0 >= self.change => Some(
+                format!("failure rate went from {:.0}% to {:.0}% over {} iterations",
+                        before * 100.0, now * 100.0, self.window)),
+            _ => None,
+        }
+    }
+}
+
+/// Parse `<duration|failure-rate> +<percent>% over <iterations>`.
+pub fn parse_trend(input: &str) -> Result<Trend, String> {
+    let invalid = || format!("expected '<duration|failure-rate> +<percent>% over <n>', got '{}'", input);
+    let words: Vec<&str> = input.split_whitespace().collect();
+    if words.len() != 4 || words[2] != "over" {
+        return Err(invalid());
+    }
+    let metric = match words[0] {
+        "duration" => Metric::Duration,
+        "failure-rate" => Metric::FailureRate,
+        _ => return Err(invalid()),
+    };
+    let change = words[1].trim_start_matches('+').trim_end_matches('%').parse::<f64>()
+        .map_err(|_| invalid())?;
+    let window = words[3].parse::<usize>().map_err(|_| invalid())?;
+    if window == 0 {
+        return Err(invalid());
+    }
+    Ok(Trend { metric, change, window, baseline: Vec::new(), recent: VecDeque::new() })
+}
+
+fn mean<I: ExactSizeIterator<Item = f64>>(values: I) -> f64 {
+    let len = values.len();
+    values.sum::<f64>() / len as f64
+}
+
+/// `--until-failure-rate`: the share of failures among the last `window`
+/// iterations.
+#[derive(Debug)]
+pub struct FailureRate {
+    threshold: f64,
+    window: usize,
+    recent: VecDeque<bool>,
+}
+
+impl FailureRate {
+    /// Record an iteration, returning the failure rate once it reaches the
+    /// threshold over a full window.
+    pub fn record(&mut self, success: bool) -> Option<f64> {
+        self.recent.push_back(success);
+        if self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < self.window {
+            return None;
+        }
+        let failures = self.recent.iter().filter(|success| !**success).count();
+        let rate = failures as f64 / self.window as f64;
+        if rate >= self.threshold {
+            Some(rate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parse `<rate>/<iterations>`, ex. `0.5/20`.
+pub fn parse_failure_rate(input: &str) -> Result<FailureRate, String> {
+    let invalid = || format!("expected '<rate>/<iterations>', ex. 0.5/20, got '{}'", input);
+    let mut parts = input.splitn(2, '/');
+    let threshold = parts.next().and_then(|t| t.parse::<f64>().ok()).ok_or_else(invalid)?;
+    let window = parts.next().and_then(|w| w.parse::<usize>().ok()).ok_or_else(invalid)?;
+    if window == 0 {
+        return Err(invalid());
+    }
+    Ok(FailureRate { threshold, window, recent: VecDeque::new() })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+/// A numeric threshold which has to hold for a while, ex. `>90 for 1m`.
+#[derive(Debug)]
+pub struct Threshold {
+    comparison: Comparison,
+    value: f64,
+    hold: Duration,
+}
+
+impl Threshold {
+    fn holds(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.value,
+            Comparison::AtLeast => value >= self.value,
+            Comparison::Below => value < self.value,
+            Comparison::AtMost => value <= self.value,
+        }
+    }
+}
+
+/// Parse `<comparison><number> [for <duration>]`, ex. `>=90 for 1m`.
+pub fn parse_threshold(input: &str) -> Result<Threshold, String> {
+    let invalid = || format!("expected '<|<=|>|>=<number> [for <duration>]', ex. '>90 for 1m', got '{}'", input);
+    let (condition, hold) = match input.find(" for ") {
+        Some(i) => (&input[..i], parse_duration(input[i + 5..].trim()).map_err(|_| invalid())?),
+        None => (input, Duration::from_secs(0)),
+    };
+    let condition = condition.trim();
+    let (comparison, value) = if let Some(value) = condition.strip_prefix(">=") {
+        (Comparison::AtLeast, value)
+    } else if let Some(value) = condition.strip_prefix("<=") {
+        (Comparison::AtMost, value)
+    } else if let Some(value) = condition.strip_prefix('>') {
+        (Comparison::Above, value)
+    } else if let Some(value) = condition.strip_prefix('<') {
+        (Comparison::Below, value)
+    } else {
+        return Err(invalid());
+    };
+    let value = value.trim().parse::<f64>().map_err(|_| invalid())?;
+    Ok(Threshold { comparison, value, hold })
+}
+
+/// `--enter`/`--exit`: an alarm on a numeric metric with hysteresis. Once
+/// the enter threshold is met the alarm is pending, until the exit
+/// threshold (by default, the enter threshold no longer holding) resets it.
+#[derive(Debug)]
+pub struct Hysteresis {
+    enter: Threshold,
+    exit: Option<Threshold>,
+    entered: Option<Instant>,
+}
+
+impl Hysteresis {
+    pub fn new(enter: Threshold, exit: Option<Threshold>) -> Hysteresis {
+        Hysteresis { enter, exit, entered: None }
+    }
+
+    /// Record a value, returning a description once the alarm was pending
+    /// for the enter threshold's hold time.
+    pub fn record(&mut self, value: f64, now: Instant) -> Option<String> {
+        match self.entered {
+            None if self.enter.holds(value) => self.entered = Some(now),
+            None => {}
+            Some(_) => {
+                let left = match self.exit {
+                    Some(ref exit) => exit.holds(value),
+                    None => !self.enter.holds(value),
+                };
+                if left {
+                    self.entered = None;
+                }
+            }
+        }
+        let pending = now.duration_since(self.entered?);
+        if pending >= self.enter.hold {
+            let pending = Duration::from_millis(pending.as_millis() as u64);
+            Some(format!("{} after {} beyond the threshold", value, format_duration(pending)))
+        } else {
+            None
+        }
+    }
+}
+
+/// The first number in `output`.
+pub fn first_number(output: &str) -> Option<f64> {
+    output.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .find_map(|word| word.parse::<f64>().ok())
+}
+
+/// `--aggregate`: a number captured from the output of every iteration.
+#[derive(Debug)]
+pub struct Aggregate {
+    pattern: Regex,
+    values: Vec<f64>,
+}
+
+/// Parse a regex capturing the number to aggregate in its only group.
+pub fn parse_aggregate(input: &str) -> Result<Aggregate, String> {
+    let pattern = Regex::new(input).map_err(|e| e.to_string())?;
+    if pattern.captures_len() != 2 {
+        return Err(format!("expected a regex with one capture group, got '{}'", input));
+    }
+    Ok(Aggregate { pattern, values: Vec::new() })
+}
+
+/// The value at percentile `p` of `sorted`, the nearest one ranked.
+fn nearest_rank(sorted: &[f64], p: f64) -> f64 {
+    sorted[((p / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1]
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    sorted
+}
+
+/// A number with up to three decimals, without trailing zeros.
+pub fn format_number(value: f64) -> String {
+    let formatted = format!("{:.3}", value);
+    formatted.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+impl Aggregate {
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// Record the number captured first in `output`, if any.
+    pub fn record(&mut self, output: &str) {
+        let value = self.pattern.captures(output)
+            .and_then(|captures| captures.get(1))
+            .and_then(|number| number.as_str().trim().parse::<f64>().ok());
+        if let Some(value) = value {
+            self.values.push(value);
+        }
+    }
+
+    /// The statistics of the recorded numbers as summary lines; percentiles
+    /// are the nearest recorded values.
+    pub fn report(&self) -> Vec<(&'static str, String)> {
+        let sorted = sorted(&self.values);
+        let mut report = vec![("Values", sorted.len().to_string())];
+        if sorted.is_empty() {
+            return report;
+        }
+        let sum: f64 = sorted.iter().sum();
+        report.extend(vec![
+            ("Min", format_number(sorted[0])),
+            ("Max", format_number(sorted[sorted.len() - 1])),
+            ("Mean", format_number(sum / sorted.len() as f64)),
+            ("Sum", format_number(sum)),
+            ("p50", format_number(nearest_rank(&sorted, 50.0))),
+            ("p90", format_number(nearest_rank(&sorted, 90.0))),
+            ("p99", format_number(nearest_rank(&sorted, 99.0))),
+        ]);
+        report
+    }
+
+    /// Describe the first of `limits` crossed by the latest `window` values,
+    /// or all of them.
+    pub fn crossed(&self, limits: &[AggregateLimit], window: Option<usize>) -> Option<String> {
+        let recent = &self.values[self.values.len().saturating_sub(window.unwrap_or(self.values.len()))..];
+        if recent.is_empty() {
+            return None;
+        }
+        limits.iter().find_map(|limit| {
+            let value = match limit.statistic {
+                Statistic::Mean => mean(recent.iter().cloned()),
+                Statistic::Percentile(p) => nearest_rank(&sorted(recent), p),
+            };
+            let crossed = if limit.above { value > limit.value } else { value < limit.value };
+            if crossed {
+                Some(format!("{} {} is {} {}", limit.name, format_number(value),
+                             if limit.above { "above" } else { "below" }, format_number(limit.value)))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Statistic {
+    Mean,
+    Percentile(f64),
+}
+
+/// `--until-mean-gt` and the like: a limit on a statistic of the
+/// `--aggregate` numbers.
+#[derive(Debug)]
+pub struct AggregateLimit {
+    pub name: &'static str,
+    pub statistic: Statistic,
+    pub above: bool,
+    pub value: f64,
+}
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// `--plot`: `values` as a sparkline of at most `width` characters, each
+/// the mean of its share of the values.
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    let columns: Vec<f64> = if values.len() <= width {
+        values.to_vec()
+    } else {
+        (0..width).map(|i| mean(values[i * values.len() / width..(i + 1) * values.len() / width].iter().cloned()))
+            .collect()
+    };
+    let min = columns.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = columns.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    columns.iter()
+        .map(|value| {
+            let level = if max > min { (value - min) / (max - min) * (SPARKS.len() - 1) as f64 } else { 0.0 };
+            SPARKS[level.round() as usize]
+        })
+        .collect()
+}
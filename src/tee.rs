@@ -0,0 +1,94 @@
+//! The output and the error output of the command kept apart, for
+//! `--match-stream`, `--discard-stderr` and `--expect-file`, while their
+//! lines are written to a single file in the order they arrive.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::thread::{self, JoinHandle};
+
+use libc;
+
+/// Copies the lines of both streams of a command to its output file.
+pub struct Tee {
+    reader: JoinHandle<io::Result<Vec<bool>>>,
+}
+
+/// A stream of the command and its line read so far.
+struct Stream {
+    file: Option<fs::File>,
+    line: Vec<u8>,
+    is_stderr: bool,
+}
+
+impl Stream {
+    /// Write the complete lines read so far to `output`, and with `eof`
+    /// the rest as a line of its own.
+    fn flush(&mut self, output: &mut fs::File, from_stderr: &mut Vec<bool>, eof: bool) -> io::Result<()> {
+        while let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+            output.write_all(&self.line[..=end])?;
+            from_stderr.push(self.is_stderr);
+            self.line.drain(..=end);
+        }
+        if eof && !self.line.is_empty() {
+            self.line.push(b'\n');
+            self.flush(output, from_stderr, false)?;
+        }
+        Ok(())
+    }
+}
+
+impl Tee {
+    /// Copy the lines of `stdout` and `stderr` to `output` as they arrive,
+    /// those of stdout first when both have some. A last line without a
+    /// newline gets one, so that lines of the two streams don't run into
+    /// each other.
+    pub fn start(stdout: fs::File, stderr: fs::File, mut output: fs::File) -> Tee {
+        let reader = thread::spawn(move || {
+            let mut streams = [
+                Stream { file: Some(stdout), line: Vec::new(), is_stderr: false },
+                Stream { file: Some(stderr), line: Vec::new(), is_stderr: true },
+            ];
+            let mut from_stderr = Vec::new();
+            let mut buffer = [0; 8192];
+            while streams.iter().any(|stream| stream.file.is_some()) {
+                let mut fds: Vec<libc::pollfd> = streams.iter()
+                    .map(|stream| libc::pollfd {
+                        fd: stream.file.as_ref().map_or(-1, AsRawFd::as_raw_fd),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    })
+                    .collect();
+                if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+                    let error = io::Error::last_os_error();
+                    if error.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(error);
+                }
+                for (stream, fd) in streams.iter_mut().zip(&fds) {
+                    if fd.revents == 0 {
+                        continue;
+                    }
+                    let read = match stream.file.as_mut() {
+                        Some(file) => file.read(&mut buffer)?,
+                        None => continue,
+                    };
+                    stream.line.extend_from_slice(&buffer[..read]);
+                    if read == 0 {
+                        stream.file = None;
+                    }
+                    stream.flush(&mut output, &mut from_stderr, read == 0)?;
+                }
+            }
+            Ok(from_stderr)
+        });
+        Tee { reader }
+    }
+
+    /// Wait for both streams to end. For every line of the output, whether
+    /// it came from stderr.
+    pub fn finish(self) -> io::Result<Vec<bool>> {
+        self.reader.join().unwrap_or_else(|_| Err(io::Error::other("reading the output failed")))
+    }
+}
@@ -0,0 +1,161 @@
+//! `{placeholder}` templates rendered once per iteration.
+//!
+//! Supported placeholders are `{count}`, `{actualcount}` (or `{i}`), `{item}`,
+//! `{tag}` and `{now}` / `{now:<strftime format>}`. Literal braces are written as
+//! `{{` and `}}`.
+
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::c_char;
+use std::time::SystemTime;
+
+use libc;
+
+use time::local_time;
+
+extern "C" {
+    fn strftime(s: *mut c_char, max: libc::size_t, format: *const c_char, tm: *const libc::tm) -> libc::size_t;
+}
+
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Count,
+    ActualCount,
+    Item,
+    Tag,
+    Now(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// The values a template can refer to.
+pub struct Context<'a> {
+    pub count: &'a str,
+    pub actual_count: usize,
+    pub item: Option<&'a str>,
+    /// The iteration's `--tag`
+    pub tag: Option<&'a str>,
+    pub now: SystemTime,
+}
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = input.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(format!("unterminated placeholder in '{}'", input)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(literal.split_off(0)));
+                    }
+                    parts.push(parse_placeholder(&placeholder)?);
+                }
+                '}' => return Err(format!("unmatched '}}' in '{}'", input)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    pub fn render(&self, context: &Context) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => rendered.push_str(s),
+                Part::Count => rendered.push_str(context.count),
+                Part::ActualCount => rendered.push_str(&context.actual_count.to_string()),
+                Part::Item => rendered.push_str(context.item.unwrap_or("")),
+                Part::Tag => rendered.push_str(context.tag.unwrap_or("")),
+                Part::Now(format) => rendered.push_str(&format_time(context.now, format)),
+            }
+        }
+        rendered
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Part, String> {
+    let (name, arg) = match placeholder.find(':') {
+        Some(i) => (&placeholder[..i], Some(&placeholder[i + 1..])),
+        None => (placeholder, None),
+    };
+    match (name, arg) {
+        ("count", None) => Ok(Part::Count),
+        ("actualcount", None) | ("i", None) => Ok(Part::ActualCount),
+        ("item", None) => Ok(Part::Item),
+        ("tag", None) => Ok(Part::Tag),
+        ("now", None) => Ok(Part::Now(DEFAULT_TIME_FORMAT.to_owned())),
+        ("now", Some(format)) => Ok(Part::Now(format.to_owned())),
+        _ => Err(format!("unknown placeholder '{{{}}}'", placeholder)),
+    }
+}
+
+/// Format `time` in the local timezone with a `strftime(3)` format string.
+pub fn format_time(time: SystemTime, format: &str) -> String {
+    let format = match CString::new(format) {
+        Ok(f) => f,
+        Err(_) => return String::new(),
+    };
+
+    let mut buf = vec![0u8; 256];
+    let tm = local_time(time);
+    unsafe {
+        let len = strftime(buf.as_mut_ptr() as *mut c_char, buf.len(), format.as_ptr(), &tm);
+        buf.truncate(len);
+    }
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+/// An environment variable whose value is rendered from a template,
+/// given as `KEY=TEMPLATE` on the command line.
+#[derive(Debug, Clone)]
+pub struct EnvTemplate {
+    pub key: String,
+    pub template: Template,
+}
+
+pub fn parse_env_template(input: &str) -> Result<EnvTemplate, String> {
+    match input.find('=') {
+        Some(0) | None => Err(format!("expected KEY=TEMPLATE, got '{}'", input)),
+        Some(i) => Ok(EnvTemplate {
+            key: input[..i].to_owned(),
+            template: Template::parse(&input[i + 1..])?,
+        }),
+    }
+}
+
+/// Parse `--env-template-file`: one `KEY=TEMPLATE` per line, skipping blank
+/// lines and `#` comments.
+pub fn parse_env_template_file(path: &str) -> Result<Vec<EnvTemplate>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    contents.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| parse_env_template(line.trim()).map_err(|e| format!("{}:{}: {}", path, number + 1, e)))
+        .collect()
+}
@@ -0,0 +1,264 @@
+//! Points in time given on the command line.
+
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use humantime::parse_duration;
+use libc;
+
+extern "C" {
+    fn tzset();
+}
+
+pub const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+pub const WEEKDAYS: [&str; 7] = ["sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"];
+
+/// Break `time` down in the local timezone.
+pub fn local_time(time: SystemTime) -> libc::tm {
+    broken_down(time, false)
+}
+
+/// Break `time` down in UTC or the local timezone.
+fn broken_down(time: SystemTime, utc: bool) -> libc::tm {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as libc::time_t,
+        Err(_) => 0,
+    };
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        if utc {
+            libc::gmtime_r(&secs, &mut tm);
+        } else {
+            tzset();
+            libc::localtime_r(&secs, &mut tm);
+        }
+        tm
+    }
+}
+
+/// The point in time of a `tm` in UTC or the local timezone, normalizing
+/// out-of-range fields.
+fn from_broken_down(mut tm: libc::tm, utc: bool) -> SystemTime {
+    tm.tm_isdst = -1;
+    let secs = unsafe { if utc { libc::timegm(&mut tm) } else { libc::mktime(&mut tm) } };
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// `--until-time`, a point in time or a time on the clock, read in the local
+/// timezone unless `--utc` is given.
+#[derive(Debug, Clone)]
+pub enum UntilTime {
+    At(SystemTime),
+    Clock(Clock),
+}
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    day: Day,
+    hour: libc::c_int,
+    minute: libc::c_int,
+    second: libc::c_int,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Day {
+    /// The next time the clock shows the time
+    Next,
+    Today,
+    Tomorrow,
+    Weekday(libc::c_int),
+    Date { year: libc::c_int, month: libc::c_int, day: libc::c_int },
+}
+
+/// Parse `--until-time`: a date, optionally with a time of day and an UTC
+/// offset, ex. `2024-06-01`, `2024-06-01 17:30` or `2024-06-01T17:30:00+02:00`,
+/// a time of day optionally preceded by `today`, `tomorrow` or a weekday,
+/// ex. `14:30`, `6pm`, `tomorrow 06:00`, `friday 17:00`, a duration from
+/// now, ex. `+2h30m`, or seconds since the Unix epoch, ex. `@1718000000`.
+pub fn parse_until_time(input: &str) -> Result<UntilTime, String> {
+    if let Some(duration) = input.trim().strip_prefix('+') {
+        return parse_duration(duration.trim())
+            .map(|duration| UntilTime::At(SystemTime::now() + duration))
+            .map_err(|e| format!("invalid duration '{}': {}", input, e));
+    }
+    // The @ is optional, as a number alone is no time of day
+    let epoch = input.trim().strip_prefix('@').unwrap_or(input.trim());
+    if epoch.starts_with(|c: char| c.is_ascii_digit()) && epoch.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return epoch.parse::<f64>().ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .map(|since| UntilTime::At(UNIX_EPOCH + since))
+            .ok_or_else(|| format!("invalid Unix time '{}'", input));
+    }
+    parse_date_time(input.trim())
+        .or_else(|| parse_clock(input))
+        .ok_or_else(|| {
+            format!("expected a date like '2018-05-25 20:50:00' or a time like 'friday 17:00', got '{}'", input)
+        })
+}
+
+/// Parse `YYYY-MM-DD[( |T)HH:MM[:SS[.FRACTION]][Z|(+|-)HH[:]MM]]`.
+fn parse_date_time(input: &str) -> Option<UntilTime> {
+    let (date, time) = match input.find(['T', 't', ' ']) {
+        Some(i) => (&input[..i], Some(input[i + 1..].trim())),
+        None => (input, None),
+    };
+    let fields: Vec<libc::c_int> = date.split('-').map(|f| f.parse().ok()).collect::<Option<_>>()?;
+    let (year, month, day) = match fields.as_slice() {
+        [year, month, day] if (1..=12).contains(month) && (1..=31).contains(day) => (*year, *month, *day),
+        _ => return None,
+    };
+    let day = Day::Date { year, month, day };
+    let time = match time {
+        Some(time) => time,
+        None => return Some(UntilTime::Clock(Clock { day, hour: 0, minute: 0, second: 0 })),
+    };
+    // The UTC offset, in seconds east
+    let (time, offset) = if let Some(time) = time.strip_suffix(['Z', 'z']) {
+        (time, Some(0))
+    } else if let Some(i) = time.rfind(['+', '-']) {
+        let digits: String = time[i + 1..].chars().filter(|&c| c != ':').collect();
+        if digits.len() != 4 {
+            return None;
+        }
+        let hours: i64 = digits[..2].parse().ok()?;
+        let minutes: i64 = digits[2..].parse().ok()?;
+        let sign = if time[i..].starts_with('-') { -1 } else { 1 };
+        (&time[..i], Some(sign * (hours * 3600 + minutes * 60)))
+    } else {
+        (time, None)
+    };
+    let time = time.split('.').next().unwrap_or(time);
+    if time.ends_with("am") || time.ends_with("pm") {
+        return None;
+    }
+    let (hour, minute, second) = parse_time_of_day(time)?;
+    let clock = Clock { day, hour, minute, second };
+    Some(match offset {
+        Some(offset) => {
+            let utc = clock.resolve(true, SystemTime::now());
+            let time = if offset >= 0 {
+                utc.checked_sub(Duration::from_secs(offset as u64))
+            } else {
+                utc.checked_add(Duration::from_secs(offset.unsigned_abs()))
+            };
+            UntilTime::At(time?)
+        }
+        None => UntilTime::Clock(clock),
+    })
+}
+
+/// Parse a time of day, optionally preceded by a day.
+fn parse_clock(input: &str) -> Option<UntilTime> {
+    let input = input.to_lowercase();
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let (day, time) = match words.as_slice() {
+        [time] => (Day::Next, *time),
+        [day, time] => {
+            let day = match *day {
+                "today" => Day::Today,
+                "tomorrow" => Day::Tomorrow,
+                day => WEEKDAYS.iter()
+                    .position(|name| *name == day || (day.len() == 3 && name.starts_with(day)))
+                    .map(|weekday| Day::Weekday(weekday as libc::c_int))?,
+            };
+            (day, *time)
+        }
+        _ => return None,
+    };
+    let (hour, minute, second) = parse_time_of_day(time)?;
+    Some(UntilTime::Clock(Clock { day, hour, minute, second }))
+}
+
+impl UntilTime {
+    /// The point in time, with clock times read in UTC or the local
+    /// timezone, relative to `now`.
+    pub fn resolve(&self, utc: bool, now: SystemTime) -> SystemTime {
+        match self {
+            UntilTime::At(time) => *time,
+            UntilTime::Clock(clock) => clock.resolve(utc, now),
+        }
+    }
+}
+
+impl Clock {
+    /// Without a day, the time is the next time the clock shows it; a
+    /// weekday is its next occurrence.
+    fn resolve(&self, utc: bool, now: SystemTime) -> SystemTime {
+        let mut tm = broken_down(now, utc);
+        let today = tm.tm_wday;
+        tm.tm_hour = self.hour;
+        tm.tm_min = self.minute;
+        tm.tm_sec = self.second;
+        match self.day {
+            Day::Next | Day::Today => {}
+            Day::Tomorrow => tm.tm_mday += 1,
+            Day::Weekday(weekday) => tm.tm_mday += (weekday - today + 7) % 7,
+            Day::Date { year, month, day } => {
+                tm.tm_year = year - 1900;
+                tm.tm_mon = month - 1;
+                tm.tm_mday = day;
+            }
+        }
+        let time = from_broken_down(tm, utc);
+        if time > now {
+            return time;
+        }
+        match self.day {
+            Day::Next => tm.tm_mday += 1,
+            Day::Weekday(_) => tm.tm_mday += 7,
+            Day::Today | Day::Tomorrow | Day::Date { .. } => return time,
+        }
+        from_broken_down(tm, utc)
+    }
+}
+
+/// Parse `HH:MM[:SS]` or a 12-hour time like `6pm` or `5:30am`.
+fn parse_time_of_day(input: &str) -> Option<(libc::c_int, libc::c_int, libc::c_int)> {
+    let (clock, offset) = if let Some(clock) = input.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = input.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (input, None)
+    };
+    let fields: Vec<libc::c_int> = clock.split(':').map(|f| f.parse().ok()).collect::<Option<_>>()?;
+    let (hour, minute, second) = match (fields.as_slice(), offset) {
+        ([hour], Some(_)) => (*hour, 0, 0),
+        ([hour, minute], _) => (*hour, *minute, 0),
+        ([hour, minute, second], _) => (*hour, *minute, *second),
+        _ => return None,
+    };
+    let hour = match offset {
+        Some(offset) if (1..=12).contains(&hour) => hour % 12 + offset,
+        Some(_) => return None,
+        None => hour,
+    };
+    if hour < 24 && minute < 60 && second < 60 && hour >= 0 && minute >= 0 && second >= 0 {
+        Some((hour, minute, second))
+    } else {
+        None
+    }
+}
+
+/// Parse an HTTP date in the preferred format of RFC 7231, ex.
+/// `Wed, 21 Oct 2015 07:28:00 GMT`.
+pub fn parse_http_date(input: &str) -> Option<SystemTime> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let (day, month, year, time) = match words.as_slice() {
+        [_, day, month, year, time, "GMT"] => (day, month.to_lowercase(), year, time),
+        _ => return None,
+    };
+    let (hour, minute, second) = parse_time_of_day(time)?;
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        tm.tm_mday = day.parse().ok()?;
+        tm.tm_mon = MONTHS.iter().position(|name| *name == month)? as libc::c_int;
+        tm.tm_year = year.parse::<libc::c_int>().ok()? - 1900;
+        tm.tm_hour = hour;
+        tm.tm_min = minute;
+        tm.tm_sec = second;
+        let secs = libc::timegm(&mut tm);
+        Some(UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64))
+    }
+}
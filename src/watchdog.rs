@@ -0,0 +1,159 @@
+//! Supervision of the command while an iteration runs.
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, Instant};
+
+use humantime::format_duration;
+use libc;
+use subprocess::{self, ExitStatus, Popen};
+
+use procfs;
+use signal;
+
+/// How often limits are checked while the command runs.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Resource limits applied to every iteration.
+#[derive(Debug, Default)]
+pub struct Limits {
+    /// `--kill-if-mem`, in bytes
+    pub max_memory: Option<u64>,
+    /// `--cpu-time-limit`
+    pub max_cpu_time: Option<Duration>,
+    /// `--timeout`
+    pub timeout: Option<Duration>,
+    /// `--max-output`, in bytes
+    pub max_output: Option<u64>,
+    /// `--kill-after`: grace period between SIGTERM and SIGKILL when the
+    /// loop kills the command, also on SIGINT
+    pub kill_after: Option<Duration>,
+}
+
+impl Limits {
+    fn is_empty(&self) -> bool {
+        self.max_memory.is_none() && self.max_cpu_time.is_none() && self.timeout.is_none()
+            && self.max_output.is_none() && self.kill_after.is_none()
+    }
+
+    fn check_output(&self, output: &fs::File) -> Option<String> {
+        let max_output = self.max_output?;
+        let size = output.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        if size > max_output {
+            Some(format!("output of {} bytes exceeded the limit of {}", size, max_output))
+        } else {
+            None
+        }
+    }
+
+    /// Describe the violated limit, if any.
+    fn check(&self, pid: u32, started: Instant, output: &fs::File) -> Option<String> {
+        if self.kill_after.is_some() && signal::interrupted() {
+            return Some("interrupted".to_owned());
+        }
+        if let Some(timeout) = self.timeout {
+            if started.elapsed() > timeout {
+                return Some(format!("ran longer than {}", format_duration(timeout)));
+            }
+        }
+        if let Some(max_memory) = self.max_memory {
+            let memory = procfs::tree_resident_memory(pid);
+            if memory > max_memory {
+                return Some(format!("memory usage of {} bytes exceeded the limit of {}", memory, max_memory));
+            }
+        }
+        if let Some(max_cpu_time) = self.max_cpu_time {
+            let cpu_time = procfs::tree_cpu_time(pid);
+            if cpu_time > max_cpu_time {
+                return Some(format!("CPU time of {} exceeded the limit of {}",
+                                    format_duration(cpu_time), format_duration(max_cpu_time)));
+            }
+        }
+        self.check_output(output)
+    }
+}
+
+/// How an iteration ended.
+pub struct Outcome {
+    pub exit_status: ExitStatus,
+    /// Why the command was killed by the loop, if it was.
+    pub killed: Option<String>,
+    /// Why the loop counts the iteration as failed although the command
+    /// succeeded, ex. its output differs from `--expect-file`.
+    pub failed: Option<String>,
+}
+
+impl Outcome {
+    pub fn success(&self) -> bool {
+        self.killed.is_none() && self.failed.is_none() && self.exit_status.success()
+    }
+
+    /// Describe why the iteration failed, if it did.
+    pub fn failure(&self) -> Option<String> {
+        match (&self.killed, &self.failed, self.exit_status) {
+            (Some(reason), _, _) => Some(format!("killed: {}", reason)),
+            (None, Some(reason), _) => Some(reason.clone()),
+            _ if self.exit_status.success() => None,
+            (None, None, ExitStatus::Exited(code)) => Some(format!("exit code {}", code)),
+            (None, None, ExitStatus::Signaled(signal)) => Some(format!("killed by signal {}", signal)),
+            (None, None, status) => Some(format!("{:?}", status)),
+        }
+    }
+}
+
+/// Wait for `child`, started at `started` and writing to `output`, to
+/// finish, killing it (and its descendants on Linux) as soon as it violates
+/// one of `limits`. Output beyond `--max-output` fails the iteration even if
+/// the command finished before it was noticed.
+pub fn supervise(child: &mut Popen, limits: &Limits, started: Instant, output: &fs::File)
+                 -> subprocess::Result<Outcome> {
+    if limits.is_empty() {
+        return Ok(Outcome { exit_status: child.wait()?, killed: None, failed: None });
+    }
+
+    let pid = child.pid();
+    loop {
+        if let Some(exit_status) = child.wait_timeout(POLL_INTERVAL)? {
+            return Ok(Outcome { exit_status, killed: None, failed: limits.check_output(output) });
+        }
+        if let Some(reason) = pid.and_then(|pid| limits.check(pid, started, output)) {
+            let exit_status = kill(child, limits.kill_after)?;
+            return Ok(Outcome { exit_status, killed: Some(reason), failed: None });
+        }
+    }
+}
+
+/// Kill `child` and, on Linux, its descendants, at once with SIGKILL, or
+/// with a `grace` period to clean up after SIGTERM.
+pub fn kill(child: &mut Popen, grace: Option<Duration>) -> subprocess::Result<ExitStatus> {
+    let pid = match child.pid() {
+        Some(pid) => pid,
+        None => return child.wait(),
+    };
+    if let Some(grace) = grace {
+        procfs::signal_tree(pid, libc::SIGTERM);
+        if let Some(exit_status) = child.wait_timeout(grace)? {
+            return Ok(exit_status);
+        }
+    }
+    procfs::signal_tree(pid, libc::SIGKILL);
+    child.wait()
+}
+
+/// Cut `output` down to `max` bytes, at a character boundary, dropping what
+/// exceeds `--max-output`.
+pub fn truncate_output(output: &mut fs::File, max: u64) -> io::Result<()> {
+    if output.metadata()?.len() <= max {
+        return Ok(());
+    }
+    // A character cut off at the limit starts in its last 3 bytes
+    let start = max.saturating_sub(3);
+    let mut tail = vec![0; (max - start) as usize];
+    output.seek(SeekFrom::Start(start))?;
+    output.read_exact(&mut tail)?;
+    let cut = match tail.iter().rposition(|byte| byte & 0xc0 != 0x80) {
+        Some(lead) if lead + (tail[lead].leading_ones() as usize).max(1) > tail.len() => start + lead as u64,
+        _ => max,
+    };
+    output.set_len(cut)
+}
@@ -1,4 +1,6 @@
 extern crate assert_cmd;
+extern crate predicates;
+extern crate tempfile;
 use assert_cmd::Command;
 
 fn test_stdout(args: Vec<&str>, expected_stdout: &'static str){
@@ -7,6 +9,12 @@ fn test_stdout(args: Vec<&str>, expected_stdout: &'static str){
      .assert().success().stdout(expected_stdout);
 }
 
+/// Send SIGINT to `child` after `millis` milliseconds.
+fn interrupt_after(child: &std::process::Child, millis: u64){
+    std::thread::sleep(std::time::Duration::from_millis(millis));
+    std::process::Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+}
+
 #[test]
 fn item(){
     test_stdout(
@@ -16,6 +24,271 @@ fn item(){
          c\n");
 }
 
+#[test]
+fn for_product(){
+    test_stdout(
+        vec!["--for", "a,b", "--for", "1,2,3", "echo $ITEM1-$ITEM2 $ITEM"],
+        "a-1 a,1\n\
+         a-2 a,2\n\
+         a-3 a,3\n\
+         b-1 b,1\n\
+         b-2 b,2\n\
+         b-3 b,3\n");
+}
+
+#[test]
+fn batch(){
+    for streamed in [false, true] {
+        let mut args = vec!["--batch", "2", "--", "echo \"$ITEMS|$ITEM1|$ITEM2\""];
+        if streamed {
+            args.splice(0..0, ["--queue-max", "3"]);
+        }
+        Command::cargo_bin("loop").unwrap()
+            .args(args)
+            .write_stdin("a\nb\nc\nd\ne\n")
+            .assert().success()
+            .stdout("a b|a|b\n\
+                     c d|c|d\n\
+                     e|e|\n");
+    }
+}
+
+#[test]
+fn sweep(){
+    test_stdout(
+        vec!["--for", "a,b", "--for", "1,2,3", "--stop-on-first-success-per-item", "--",
+             "echo $ITEM; test $ITEM = a,2 -o $ITEM = b,1"],
+        "a,1\na,2\nb,1\n\
+         a:\tsucceeded with a,2 after 2 attempt(s)\n\
+         b:\tsucceeded with b,1 after 1 attempt(s)\n");
+    test_stdout(
+        vec!["--for", "a,b", "--cycle", "--stop-on-first-success-per-item", "--",
+             "echo $ITEM$COUNT; test $ITEM$COUNT = a2 -o $ITEM$COUNT = b5"],
+        "a0\nb1\na2\nb3\nb5\n\
+         a:\tsucceeded with a after 2 attempt(s)\n\
+         b:\tsucceeded with b after 3 attempt(s)\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for", "a,b", "--cycle", "--every", "100ms", "--budget", "250ms", "--", "echo $ITEM; false"])
+        .assert().success()
+        .stdout("a\nb\na\n\
+                 a:\tno success in 2 attempt(s)\n\
+                 b:\tno success in 1 attempt(s)\n")
+        .stderr("Budget of 250ms spent\n");
+}
+
+#[test]
+fn jitter(){
+    // Four waits of 100ms to 300ms.
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--num", "5", "--every", "200ms", "--jitter", "50%", "--", "echo $COUNT"], "0\n1\n2\n3\n4\n");
+    let elapsed = start.elapsed();
+    assert!(elapsed >= std::time::Duration::from_millis(400));
+    assert!(elapsed < std::time::Duration::from_millis(1500));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--jitter", "150%", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn every_aligned(){
+    // Every iteration starts shortly after a multiple of 200ms on the clock.
+    let output = Command::cargo_bin("loop").unwrap()
+        .args(["--num", "3", "--every", "200ms", "--every-aligned", "--", "echo $(( $(date +%s%N) / 1000000 % 200 ))"])
+        .output().unwrap();
+    let offsets: Vec<u64> = String::from_utf8(output.stdout).unwrap().lines().map(|l| l.parse().unwrap()).collect();
+    assert_eq!(offsets.len(), 3);
+    assert!(offsets.iter().all(|&offset| offset < 100), "{:?}", offsets);
+    Command::cargo_bin("loop").unwrap()
+        .args(["--every-aligned", "--jitter", "1s", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn every_start(){
+    // The tick at 300ms is missed, the iterations start at 0, 600ms and
+    // 900ms instead of 0, 400ms and 700ms.
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--for", "0.4,0,0", "--every", "300ms", "--every-start", "--", "sleep $ITEM; echo $ITEM"],
+                "0.4\n0\n0\n");
+    let elapsed = start.elapsed();
+    assert!(elapsed >= std::time::Duration::from_millis(850));
+    assert!(elapsed < std::time::Duration::from_millis(1400));
+}
+
+#[test]
+fn cron(){
+    for expression in ["61 * * * *", "* * *", "*/0 * * * *", "0 0 30 feb *", "5-1 * * * *"] {
+        Command::cargo_bin("loop").unwrap()
+            .args(["--cron", expression, "--", "true"])
+            .assert().code(64);
+    }
+    Command::cargo_bin("loop").unwrap()
+        .args(["--cron", "@hourly", "--every", "1s", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn chaos(){
+    test_stdout(vec!["--num", "3", "--chaos", "0%", "--", "echo $CHAOS_FAIL $CHAOS_DELAY_MS"], "0 0\n0 0\n0 0\n");
+    test_stdout(vec!["--num", "3", "--chaos", "100%", "--", "echo $CHAOS_FAIL"], "1\n1\n1\n");
+    let run = || {
+        let output = Command::cargo_bin("loop").unwrap()
+            .args(["--num", "20", "--chaos", "50%", "--chaos-delay", "10ms", "--seed", "7", "--", "echo $CHAOS_FAIL $CHAOS_DELAY_MS"])
+            .output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let output = run();
+    assert_eq!(output, run());
+    let lines: Vec<(u32, u32)> = output.lines()
+        .map(|line| line.split_once(' ').map(|(fail, delay)| (fail.parse().unwrap(), delay.parse().unwrap())).unwrap())
+        .collect();
+    assert!(lines.iter().any(|&(fail, _)| fail == 0) && lines.iter().any(|&(fail, _)| fail == 1));
+    assert!(lines.iter().all(|&(_, delay)| delay <= 10));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--chaos", "120%", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn max_per(){
+    // A burst of 2, then one every 100ms.
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--num", "5", "--max-per", "2/200ms", "--", "echo $COUNT"], "0\n1\n2\n3\n4\n");
+    let elapsed = start.elapsed();
+    assert!(elapsed >= std::time::Duration::from_millis(300));
+    assert!(elapsed < std::time::Duration::from_millis(900));
+    test_stdout(vec!["--num", "2", "--max-per", "10/s", "--", "echo $COUNT"], "0\n1\n");
+    for rate in ["0/1m", "10", "10/soon"] {
+        Command::cargo_bin("loop").unwrap()
+            .args(["--max-per", rate, "--", "true"])
+            .assert().code(64);
+    }
+}
+
+#[test]
+fn backoff(){
+    // Waits of 200ms, 300ms (capped) and 300ms after the failures, then
+    // 100ms after the success.
+    let start = std::time::Instant::now();
+    test_stdout(
+        vec!["--for", "false,false,false,true,true", "--every", "100ms", "--backoff", "2..300ms", "--", "echo $ITEM; $ITEM"],
+        "false\nfalse\nfalse\ntrue\ntrue\n");
+    let elapsed = start.elapsed();
+    assert!(elapsed >= std::time::Duration::from_millis(900));
+    assert!(elapsed < std::time::Duration::from_millis(1400));
+    // Without --every, the interval grows from a second.
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--for", "false,true", "--backoff", "2", "--", "echo $ITEM; $ITEM"], "false\ntrue\n");
+    let elapsed = start.elapsed();
+    assert!(elapsed >= std::time::Duration::from_millis(900));
+    assert!(elapsed < std::time::Duration::from_millis(1800));
+    // An interval of zero stays zero, however large the factor.
+    test_stdout(vec!["--num", "3", "--every", "0s", "--backoff", "1e300", "--", "echo x; false"], "x\nx\nx\n");
+}
+
+#[test]
+fn cycle(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("endpoints");
+    std::fs::write(&file, "a\nb\n").unwrap();
+    test_stdout(vec!["--for-file", file.to_str().unwrap(), "--cycle", "--num", "5", "--", "echo $ITEM"], "a\nb\na\nb\na\n");
+    test_stdout(vec!["--for", "x,y,z", "--cycle", "--until-contains", "y", "--", "echo $ITEM$COUNT"], "x0\ny1\n");
+    test_stdout(vec!["--for", "x,y,z", "--cycle", "--until-contains", "y4", "--", "echo $ITEM$COUNT"], "x0\ny1\nz2\nx3\ny4\n");
+    test_stdout(vec!["--for", "a,b,c", "--cycle", "--rotate", "1", "--num", "9", "--", "echo $ITEM"],
+                "a\nb\nc\nb\nc\na\nc\na\nb\n");
+    test_stdout(vec!["--for-range", "1..3", "--cycle", "--rotate", "2", "--num", "6", "--", "echo $ITEM"],
+                "1\n2\n3\n3\n1\n2\n");
+}
+
+#[test]
+fn reshuffle_each_cycle(){
+    let run = || {
+        let output = Command::cargo_bin("loop").unwrap()
+            .args(["--for-range", "1..8", "--cycle", "--reshuffle-each-cycle", "--seed", "5", "--num", "40", "--", "echo $ITEM"])
+            .output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let output = run();
+    assert_eq!(output, run());
+    let passes: Vec<Vec<&str>> = output.lines().collect::<Vec<_>>().chunks(8).map(<[_]>::to_vec).collect();
+    assert_eq!(passes[0], ["1", "2", "3", "4", "5", "6", "7", "8"]);
+    for pass in &passes {
+        let mut sorted = pass.clone();
+        sorted.sort();
+        assert_eq!(sorted, passes[0]);
+    }
+    assert!(passes.windows(2).any(|pair| pair[0] != pair[1]));
+}
+
+#[test]
+fn shuffle(){
+    test_stdout(vec!["--for", "a,b,c,d,e", "--shuffle", "7", "--", "echo $ITEM"], "e\nb\nd\na\nc\n");
+    test_stdout(vec!["--for", "a,b", "--for", "1,2", "--shuffle", "7", "--", "echo $ITEM1$ITEM2"], "a2\nb1\na1\nb2\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for", "a,b", "--shuffle", "--", "echo $ITEM"])
+        .assert().success()
+        .stderr(predicates::str::starts_with("Shuffled with seed "));
+}
+
+#[test]
+fn zip(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("outputs");
+    std::fs::write(&file, "a.png\nb.png\n").unwrap();
+    test_stdout(
+        vec!["--for", "a.jpg,b.jpg,c.jpg", "--for-file", file.to_str().unwrap(), "--zip", "--", "echo $ITEM1 $ITEM2"],
+        "a.jpg a.png\n\
+         b.jpg b.png\n");
+    test_stdout(
+        vec!["--for", "x,y", "--for-file", file.to_str().unwrap(), "--", "echo $ITEM"],
+        "x,a.png\n\
+         x,b.png\n\
+         y,a.png\n\
+         y,b.png\n");
+}
+
+#[test]
+fn no_shell(){
+    test_stdout(
+        vec!["--num", "1", "--no-shell", "--", "printf", "%s\\n", "two words", "*", "$ITEM"],
+        "two words\n\
+         *\n\
+         $ITEM\n");
+}
+
+#[test]
+fn replace(){
+    test_stdout(
+        vec!["--for=a b,$HOME;c", "-I", "{}", "--", "printf '[%s]\\n' {} x{}y"],
+        "[a b]\n\
+         [xa by]\n\
+         [$HOME;c]\n\
+         [x$HOME;cy]\n");
+    test_stdout(
+        vec!["--for=a b,c", "--replace", "@", "--", "printf", "%s|\\n", "@.png"],
+        "a b.png|\n\
+         c.png|\n");
+    test_stdout(
+        vec!["--for=a b,c", "-I", "{}", "--", "sh -c 'echo \"[{}]\"' \"x y\" z\\ {}"],
+        "[a b]\n\
+         [c]\n");
+    test_stdout(
+        vec!["--for=a", "-I", "{}", "--", "printf '%s|' \"it's\" \"\\\"{}\\\"\" x\\ y ''"],
+        "it's|\"a\"|x y||\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "-I", "{}", "--", " "])
+        .assert().code(64).stderr("--replace needs a command to run\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "-I", "{}", "--", "echo 'x"])
+        .assert().code(64).stderr("Invalid command for --replace: unterminated single quote\n");
+}
+
+#[test]
+fn shell(){
+    test_stdout(vec!["--num", "1", "--shell", "bash", "--", "echo ${BASH_VERSION:+bash}"], "bash\n");
+    test_stdout(vec!["--num", "1", "--shell", "bash -o pipefail -c", "--", "false | true; echo $?"], "1\n");
+}
+
 #[test]
 fn counter() {
     test_stdout(
@@ -51,7 +324,7 @@ fn summary(){
         vec!["--for=true,false,true", "--summary", "--", "$ITEM"],
         "Total runs:\t3\n\
          Successes:\t2\n\
-         Failures:\t1 (-1)\n");
+         Failures:\t1 (1)\n");
     test_stdout(
         vec!["--for=true,true,true", "--summary", "--", "$ITEM"],
         "Total runs:\t3\n\
@@ -59,6 +332,79 @@ fn summary(){
          Failures:\t0\n");
 }
 
+#[test]
+fn aggregate(){
+    test_stdout(
+        vec!["--for=5,1,x,3,2.5", "--summary", "--aggregate", "time=([0-9.]+)", "--", "echo time=$ITEM"],
+        "time=5\n\
+         time=1\n\
+         time=x\n\
+         time=3\n\
+         time=2.5\n\
+         Total runs:\t5\n\
+         Successes:\t5\n\
+         Failures:\t0\n\
+         Values:\t4\n\
+         Min:\t1\n\
+         Max:\t5\n\
+         Mean:\t2.875\n\
+         Sum:\t11.5\n\
+         p50:\t2.5\n\
+         p90:\t5\n\
+         p99:\t5\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--summary", "--aggregate", "[0-9]+", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn until_aggregate(){
+    let values = "--for=10,12,11,30,31,32,10";
+    test_stdout(
+        vec![values, "--aggregate", "v=([0-9]+)", "--aggregate-window", "3", "--until-mean-gt", "20", "--", "echo v=$ITEM"],
+        "v=10\nv=12\nv=11\nv=30\nv=31\n");
+    test_stdout(
+        vec![values, "--aggregate", "v=([0-9]+)", "--until-mean-gt", "20", "--", "echo v=$ITEM"],
+        "v=10\nv=12\nv=11\nv=30\nv=31\nv=32\n");
+    test_stdout(
+        vec![values, "--aggregate", "v=([0-9]+)", "--until-p95-gt", "25", "--", "echo v=$ITEM"],
+        "v=10\nv=12\nv=11\nv=30\n");
+    Command::cargo_bin("loop").unwrap()
+        .args([values, "--aggregate", "v=([0-9]+)", "--until-mean-lt", "11", "--", "echo v=$ITEM"])
+        .assert().success()
+        .stdout("v=10\n")
+        .stderr("Aggregate limit crossed: mean 10 is below 11\n");
+}
+
+#[test]
+fn plot(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for=5,1,3,2.5,8,8,0", "--summary", "--plot", "--aggregate", "time=([0-9.]+)", "--", "echo time=$ITEM"])
+        .assert().success()
+        .stdout(predicates::str::ends_with("p99:\t8\nPlot:\t▅▂▄▃██▁ 0..8\n"));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--summary", "--plot", "--", "true"])
+        .assert().success()
+        .stdout(predicates::str::is_match("Failures:\t0\nPlot:\t[▁-█]{2} \\S+\\.\\.\\S+\n$").unwrap());
+}
+
+#[test]
+fn extract_kv(){
+    test_stdout(
+        vec!["--for=a,b,c", "--summary", "--extract-kv", "--",
+             "case $ITEM in a) echo 'latency=12 status=\"ok\" 1+1=2';; b) echo '{\"latency\": 15, \"rps\": 3}';; esac"],
+        "latency=12 status=\"ok\" 1+1=2\n\
+         {\"latency\": 15, \"rps\": 3}\n\
+         Total runs:\t3\n\
+         Successes:\t3\n\
+         Failures:\t0\n\
+         \n\
+         Iteration\tlatency\tstatus\trps\n\
+         0\t12\tok\t\n\
+         1\t15\t\t3\n\
+         2\t\t\t\n");
+}
+
 #[test]
 fn only_last(){
     test_stdout(
@@ -87,6 +433,45 @@ fn until_contains(){
         "ferras\n\
          ferres\n\
          ferris\n");
+    test_stdout(
+        vec!["--for=RUNNING,RUNNING,ABORTED,DONE", "--until-contains", "DONE", "--until-contains", "FAILED",
+             "-c", "ABORTED", "--", "echo $ITEM"],
+        "RUNNING\n\
+         RUNNING\n\
+         ABORTED\n");
+}
+
+#[test]
+fn until_match(){
+    test_stdout(vec!["--for=a1,b,c22", "--until-match", "[0-9]{2}", "--", "echo $ITEM"], "a1\nb\nc22\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for=a,b1,c", "--until-match", "^x", "--until-match", "[0-9]", "--", "echo $ITEM"])
+        .assert()
+        .success()
+        .stdout("a\nb1\n")
+        .stderr("Matched --until-match '[0-9]'\n");
+}
+
+#[test]
+fn until_match_count(){
+    test_stdout(vec!["--until-match-count", "5", "--until-contains", "ERROR", "--", "echo ERROR ERROR; echo ok"],
+                "ERROR ERROR\nok\nERROR ERROR\nok\nERROR ERROR\nok\n");
+    test_stdout(vec!["--until-match-count", "3", "--until-contains", "a", "--until-match", "[0-9]", "--", "echo a$COUNT"],
+                "a0\na1\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-match-count", "3", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn ignore_case_fixed_string(){
+    test_stdout(vec!["--for=a,B,c", "--ignore-case", "--until-contains", "b", "--", "echo $ITEM"], "a\nB\n");
+    test_stdout(vec!["--for=a,a.b,(c", "--fixed-string", "--until-match", "(c", "--", "echo $ITEM"], "a\na.b\n(c\n");
+    test_stdout(vec!["--for=a,axb,a.b", "--fixed-string", "--until-match", "a.b", "--", "echo $ITEM"], "a\naxb\na.b\n");
+    test_stdout(vec!["--for=x,Done.", "--fixed-string", "--ignore-case", "--while-match", "X", "--", "echo $ITEM"], "x\nDone.\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-match", "(c", "--", "true"])
+        .assert().code(64).stderr(predicates::str::starts_with("Invalid --until-match: regex parse error"));
 }
 
 #[test]
@@ -114,7 +499,15 @@ fn until_success(){
         vec!["--for=false,false,false,true,false,false", "--until-success", "--summary", "--", "$ITEM"],
         "Total runs:\t4\n\
          Successes:\t1\n\
-         Failures:\t3 (-1, -1, -1)\n");
+         Failures:\t3 (1, 1, 1)\n");
+}
+
+#[test]
+fn while_conditions(){
+    test_stdout(vec!["--for=ok,ok,down,ok", "--while-contains", "ok", "--", "echo $ITEM"], "ok\nok\ndown\n");
+    test_stdout(vec!["--for=a1,b22,c3", "--while-match", "^[a-z][0-9]$", "--", "echo $ITEM"], "a1\nb22\n");
+    test_stdout(vec!["--for=true,false,true", "--while-success", "--", "echo $ITEM; $ITEM"], "true\nfalse\n");
+    test_stdout(vec!["--for=false,true,false", "--while-fail", "--", "echo $ITEM; $ITEM"], "false\ntrue\n");
 }
 
 #[test]
@@ -123,7 +516,7 @@ fn until_fail(){
         vec!["--for=true,true,false,true,true,true", "--until-fail", "--summary", "--", "$ITEM"],
         "Total runs:\t3\n\
          Successes:\t2\n\
-         Failures:\t1 (-1)\n");
+         Failures:\t1 (1)\n");
 }
 
 #[test]
@@ -132,5 +525,1211 @@ fn until_error(){
         vec!["--for=true,true,false,true,true,true", "--until-error", "--summary", "--", "$ITEM"],
         "Total runs:\t3\n\
          Successes:\t2\n\
-         Failures:\t1 (-1)\n");
+         Failures:\t1 (1)\n");
+}
+
+#[test]
+fn env_template(){
+    test_stdout(
+        vec!["--for=a,b", "--env-template", "NAME={item}-{count}.log", "--", "echo $NAME"],
+        "a-0.log\n\
+         b-1.log\n");
+    test_stdout(
+        vec!["--num", "1", "--env-template", "YEAR={now:%Y}", "--", "echo ${#YEAR}"],
+        "4\n");
+}
+
+#[test]
+fn env_template_file(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("job.env");
+    std::fs::write(&file, "# per item\nIN={item}.csv\n\nOUT={item}-{i}.json\n").unwrap();
+    test_stdout(
+        vec!["--for=a,b", "--env-template-file", file.to_str().unwrap(), "--env-template", "OUT=override", "--",
+             "echo $IN $OUT"],
+        "a.csv override\n\
+         b.csv override\n");
+    test_stdout(
+        vec!["--for=a,b", "--env-template-file", file.to_str().unwrap(), "--", "echo $IN $OUT"],
+        "a.csv a-0.json\n\
+         b.csv b-1.json\n");
+    std::fs::write(&file, "IN={nope}\n").unwrap();
+    Command::cargo_bin("loop").unwrap()
+        .args(["--env-template-file", file.to_str().unwrap(), "--", "echo x"])
+        .assert().code(64)
+        .stderr(predicates::str::contains("job.env:1: unknown placeholder '{nope}'"));
+}
+
+#[test]
+fn checksum(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for=a,b,c", "--checksum", "sha256",
+               "--until-checksum", "0263829989B6FD954F72BAAF2FC64BC2E2F01D692D4DE72986EA808F6E99813F", "--", "echo $ITEM"])
+        .assert().success()
+        .stdout("a\nb\n")
+        .stderr("Iteration 0 sha256: 87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7\n\
+                 Iteration 1 sha256: 0263829989b6fd954f72baaf2fc64bc2e2f01d692d4de72986ea808f6e99813f\n");
+}
+
+#[test]
+fn expect_file(){
+    let dir = tempfile::tempdir().unwrap();
+    let golden = dir.path().join("golden");
+    test_stdout(vec!["--num", "1", "--expect-file", golden.to_str().unwrap(), "--update-expect", "--", "echo a; echo b"],
+                "a\nb\n");
+    assert_eq!(std::fs::read_to_string(&golden).unwrap(), "a\nb\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for=b,c", "--expect-file", golden.to_str().unwrap(), "--until-fail", "--summary", "--",
+               "echo a; echo $ITEM"])
+        .assert().success()
+        .stdout("a\nb\na\nc\n\
+                 Total runs:\t2\n\
+                 Successes:\t1\n\
+                 Failures:\t1 (99)\n")
+        .stderr(predicates::str::ends_with("differs from ".to_owned() + golden.to_str().unwrap()
+                                           + ": line 2: expected 'b', got 'c'\n"));
+
+    // The error output is neither compared nor recorded.
+    test_stdout(vec!["--num", "1", "--expect-file", golden.to_str().unwrap(), "--update-expect", "--", "echo a; echo warning >&2"],
+                "a\nwarning\n");
+    assert_eq!(std::fs::read_to_string(&golden).unwrap(), "a\n");
+    test_stdout(vec!["--num", "2", "--expect-file", golden.to_str().unwrap(), "--until-fail", "--", "echo a; echo warning >&2"],
+                "a\nwarning\na\nwarning\n");
+}
+
+#[test]
+fn seed(){
+    test_stdout(
+        vec!["--num", "3", "--seed", "40", "--", "echo $LOOP_SEED"],
+        "40\n\
+         41\n\
+         42\n");
+    test_stdout(
+        vec!["--for=true,false,false", "--seed", "7", "--summary", "--", "$ITEM"],
+        "Total runs:\t3\n\
+         Successes:\t1\n\
+         Failures:\t2 (1, 1)\n\
+         Failed seeds:\t8, 9\n");
+}
+
+#[test]
+fn stdin_broadcast(){
+    test_stdout(
+        vec!["--num", "2", "--stdin-broadcast", "hello", "--", "cat"],
+        "hello\n\
+         hello\n");
+    let dir = tempfile::tempdir().unwrap();
+    let payload = dir.path().join("payload");
+    std::fs::write(&payload, "from file\n").unwrap();
+    let file_arg = format!("@{}", payload.display());
+    test_stdout(vec!["--num", "1", "--stdin-broadcast", &file_arg, "--", "cat"], "from file\n");
+    // A string naming a file is no file without the @.
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--stdin-broadcast", payload.to_str().unwrap(), "--", "cat"])
+        .assert().success().stdout(format!("{}\n", payload.display()));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--stdin-broadcast", "@/nonexistent/payload", "--", "cat"])
+        .assert().code(74).stdout("")
+        .stderr(predicates::str::starts_with("Failed to read /nonexistent/payload"));
+}
+
+#[test]
+fn sandbox_dir(){
+    let template = env!("CARGO_MANIFEST_DIR").to_owned() + "/tests";
+    test_stdout(
+        vec!["--num", "2", "--sandbox-dir", &template, "--", "ls test.rs && rm test.rs"],
+        "test.rs\n\
+         test.rs\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--sandbox-dir", "/nonexistent/template", "--", "echo x"])
+        .assert().code(74).stdout("")
+        .stderr(predicates::str::starts_with("Failed to create sandbox from /nonexistent/template"));
+
+    // Symlinks are copied as links, even cycles and dangling ones.
+    let template = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(".", template.path().join("cycle")).unwrap();
+    std::os::unix::fs::symlink("missing", template.path().join("dangling")).unwrap();
+    test_stdout(
+        vec!["--num", "1", "--sandbox-dir", template.path().to_str().unwrap(), "--", "readlink cycle dangling"],
+        ".\n\
+         missing\n");
+}
+
+#[test]
+fn collect(){
+    let template = tempfile::tempdir().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let collect_dir = dir.path().join("artifacts");
+    test_stdout(
+        vec!["--for=0,1,0", "--sandbox-dir", template.path().to_str().unwrap(),
+             "--collect", "core-*.txt", "--collect-dir", collect_dir.to_str().unwrap(),
+             "--", "touch core-$COUNT.txt app.log; exit $ITEM"],
+        "");
+    assert!(!collect_dir.join("0").exists());
+    assert!(collect_dir.join("1/core-1.txt").exists());
+    assert!(!collect_dir.join("1/app.log").exists());
+    assert!(!collect_dir.join("2").exists());
+
+    // A --collect-dir below the working directory isn't collected from.
+    let dir = tempfile::tempdir().unwrap();
+    Command::cargo_bin("loop").unwrap()
+        .current_dir(dir.path())
+        .args(["--num", "3", "--collect", "**/*.log", "--collect-dir", "out", "--", "echo x > a.log; false"])
+        .assert().success().stderr("");
+    for count in ["0", "1", "2"] {
+        assert!(dir.path().join("out").join(count).join("a.log").exists());
+        assert!(!dir.path().join("out").join(count).join("out").exists());
+    }
+
+    // Tags are a single directory.
+    Command::cargo_bin("loop").unwrap()
+        .current_dir(dir.path())
+        .args(["--num", "1", "--tag", "../x", "--collect", "*.log", "--collect-dir", "out", "--", "false"])
+        .assert().success();
+    assert!(dir.path().join("out/.._x/0/a.log").exists());
+    assert!(!dir.path().join("x").exists());
+}
+
+#[test]
+fn min_free_space(){
+    test_stdout(
+        vec!["--num", "2", "--min-free-space", "1K", "--", "echo ok"],
+        "ok\n\
+         ok\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--min-free-space", "1000T:/", "--on-low-space", "abort", "--", "echo ok"])
+        .assert().code(73).stdout("")
+        .stderr(predicates::str::is_match("^Aborting: [0-9]+ bytes free at /, below the minimum of [0-9]+\n$").unwrap());
+}
+
+#[test]
+fn max_load(){
+    test_stdout(
+        vec!["--num", "2", "--max-load", "100000", "--max-cpu", "100%", "--", "echo ok"],
+        "ok\n\
+         ok\n");
+    for usage in ["150%", "-5", "abc"] {
+        Command::cargo_bin("loop").unwrap()
+            .args([&format!("--max-cpu={}", usage), "--", "true"])
+            .assert().code(64).stderr(predicates::str::contains(format!("'{}'", usage)));
+    }
+}
+
+#[test]
+fn power(){
+    // Without an AC adapter or battery to read, the loop isn't held back.
+    let power_supplies = std::fs::read_dir("/sys/class/power_supply").map_or(0, |entries| entries.count());
+    if cfg!(target_os = "linux") && power_supplies == 0 {
+        test_stdout(
+            vec!["--num", "2", "--only-on-ac", "--min-battery", "100%", "--", "echo ok"],
+            "ok\n\
+             ok\n");
+    }
+    for level in ["150%", "-5", "abc"] {
+        Command::cargo_bin("loop").unwrap()
+            .args([&format!("--min-battery={}", level), "--", "true"])
+            .assert().code(64).stderr(predicates::str::contains(format!("'{}'", level)));
+    }
+}
+
+#[test]
+fn require_network(){
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    test_stdout(
+        vec!["--num", "2", "--require-network", &host, "--", "echo online"],
+        "online\n\
+         online\n");
+}
+
+#[test]
+fn max_temp(){
+    test_stdout(
+        vec!["--num", "2", "--max-temp", "1000C", "--", "echo cool"],
+        "cool\n\
+         cool\n");
+}
+
+#[test]
+fn lockfile(){
+    let dir = tempfile::tempdir().unwrap();
+    let lockfile = dir.path().join("lock");
+    let lockfile = lockfile.to_str().unwrap();
+    test_stdout(
+        vec!["--num", "2", "--lockfile", lockfile, "--", "echo locked"],
+        "locked\n\
+         locked\n");
+    // The inner loop cannot take the lock held by the outer one.
+    let inner = format!("{} --num 1 --lockfile {} --lock-skip -- echo inner",
+                        env!("CARGO_BIN_EXE_loop"), lockfile);
+    test_stdout(
+        vec!["--num", "1", "--lockfile", lockfile, "--", &inner, "; echo outer"],
+        "outer\n");
+    // Waiting for a held lock can be interrupted.
+    let mut holder = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--num", "1", "--lockfile", lockfile, "--", "sleep 3"])
+        .stdin(std::process::Stdio::null())
+        .spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let waiting = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--num", "1", "--lockfile", lockfile, "--", "echo waited"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .spawn().unwrap();
+    interrupt_after(&waiting, 200);
+    let output = waiting.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    holder.kill().unwrap();
+    holder.wait().unwrap();
+}
+
+#[test]
+fn single_instance(){
+    let mut first = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--num", "1", "--single-instance", "--", "sleep 1 # single_instance test"])
+        .stdin(std::process::Stdio::null())
+        .spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--single-instance", "--", "sleep 1 # single_instance test"])
+        .assert().failure();
+    first.wait().unwrap();
+}
+
+#[test]
+fn take_over(){
+    // A loop ignoring SIGTERM is given up on after a while.
+    let stubborn = format!("trap '' TERM; exec {} --num 1 --single-instance -- 'sleep 15 # take_over test'",
+                           env!("CARGO_BIN_EXE_loop"));
+    let mut first = std::process::Command::new("sh").args(["-c", &stubborn])
+        .stdin(std::process::Stdio::null())
+        .spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--take-over", "--", "sleep 15 # take_over test"])
+        .assert().code(74)
+        .stderr(predicates::str::contains("did not exit within 10s"));
+    first.kill().unwrap();
+    first.wait().unwrap();
+}
+
+#[test]
+fn sync_start(){
+    test_stdout(
+        vec!["--num", "1", "--sync-start", "2000-01-01 00:00:00", "--", "echo go"],
+        "go\n");
+    // Times are read like --until-time.
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--num", "1", "--sync-start", "+300ms", "--", "echo go"], "go\n");
+    assert!(start.elapsed() >= std::time::Duration::from_millis(300));
+    let dir = tempfile::tempdir().unwrap();
+    let rendezvous = dir.path().join("start");
+    let waiting = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--num", "1", "--sync-start", rendezvous.to_str().unwrap(), "--", "echo go"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    std::fs::write(&rendezvous, "").unwrap();
+    let output = waiting.wait_with_output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "go\n");
+    let waiting = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--num", "1", "--sync-start", dir.path().join("never").to_str().unwrap(), "--", "echo go"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .spawn().unwrap();
+    interrupt_after(&waiting, 200);
+    let output = waiting.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+}
+
+#[test]
+fn warn_slower_than(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for=0,0.3,0", "--warn-slower-than", "200ms", "--summary", "--", "sleep $ITEM"])
+        .assert().success()
+        .stdout("Total runs:\t3\n\
+                 Successes:\t3\n\
+                 Failures:\t0\n\
+                 Slow runs:\t1\n")
+        .stderr(predicates::str::starts_with("Iteration 1 took 3"));
+}
+
+#[test]
+fn until_trend(){
+    test_stdout(
+        vec!["--for=true,true,true,false,true,false,false,true", "--until-trend", "failure-rate +100% over 2",
+             "--", "echo $COUNT; $ITEM"],
+        "0\n1\n2\n3\n4\n5\n6\n");
+}
+
+#[test]
+fn until_failure_rate(){
+    test_stdout(
+        vec!["--for=false,true,true,false,true,false,false", "--until-failure-rate", "0.5/4",
+             "--", "echo $COUNT; $ITEM"],
+        "0\n1\n2\n3\n");
+}
+
+#[test]
+fn kill_if_mem(){
+    // Each iteration tries to hold 64M in a shell variable.
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--kill-if-mem", "16M", "--summary", "--",
+               "x=$(head -c 67108864 /dev/zero | tr '\\0' x); echo survived"])
+        .assert().success()
+        .stdout("Total runs:\t1\n\
+                 Successes:\t0\n\
+                 Failures:\t1 (99)\n")
+        .stderr(predicates::str::starts_with("Killed iteration 0: memory usage"));
+}
+
+#[test]
+fn output_routing(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "3", "--discard-stderr", "--until-contains", "oops", "--", "printf out; echo oops >&2; false"])
+        .assert().success()
+        .stdout("out\n")
+        .stderr("");
+    test_stdout(vec!["--num", "1", "--discard-stderr", "--stderr-to-stdout", "--", "echo out; echo err >&2"], "out\nerr\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--stdout-to-stderr", "--summary", "--", "echo out; echo err >&2"])
+        .assert().success()
+        .stdout("Total runs:\t2\nSuccesses:\t2\nFailures:\t0\n")
+        .stderr("out\nerr\nout\nerr\n");
+}
+
+#[test]
+fn match_stream(){
+    let args = ["--num", "3", "--until-contains", "x", "--", "echo $COUNT; echo x$COUNT >&2"];
+    test_stdout([&["--match-stream", "stdout"], &args[..]].concat(), "0\nx0\n1\nx1\n2\nx2\n");
+    test_stdout([&["--match-stream", "stderr"], &args[..]].concat(), "0\nx0\n");
+    test_stdout([&["--match-stream", "both"], &args[..]].concat(), "0\nx0\n");
+    test_stdout(vec!["--match-stream", "stderr", "--discard-stderr", "--num", "3", "--until-contains", "x1", "--",
+                     "echo x$COUNT; echo x$COUNT >&2"],
+                "x0\nx1\n");
+    // The lines are printed in the order they were written.
+    test_stdout(vec!["--match-stream", "stdout", "--num", "2", "--until-contains", "1", "--",
+                     "echo x$COUNT >&2; sleep 0.1; echo $COUNT; sleep 0.1; echo y$COUNT >&2"],
+                "x0\n0\ny0\nx1\n1\ny1\n");
+    test_stdout(vec!["--discard-stderr", "--only-last", "--num", "2", "--", "echo x$COUNT >&2; echo $COUNT"], "1\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--match-stream", "stdin", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn report(){
+    use predicates::Predicate;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("report.json");
+    let report = |args: &[&str]| {
+        Command::cargo_bin("loop").unwrap()
+            .args(["--report", path.to_str().unwrap()]).args(args)
+            .assert();
+        std::fs::read_to_string(&path).unwrap()
+    };
+    let written = report(&["--num", "5", "--until-contains", "2", "--", "echo $COUNT; test $COUNT -ne 1"]);
+    assert!(predicates::str::is_match(
+        "^\\{\"config_sha256\":\"[0-9a-f]{64}\",\"started\":\"[0-9T:Z-]+\",\"ended\":\"[0-9T:Z-]+\",\
+         \"duration_secs\":[0-9.]+,\"iterations\":3,\"successes\":2,\"failures\":1,\"reason\":\"condition\",\
+         \"error\":null,\"exit_code\":0,\"last_output\":\"2\"\\}\n$").unwrap().eval(&written), "{}", written);
+    let written = report(&["--for-duration", "0s", "--error-duration", "--", "echo x"]);
+    assert!(written.contains("\"iterations\":0,"), "{}", written);
+    assert!(written.contains("\"reason\":\"duration\",\"error\":\"Timed out after 0s\",\"exit_code\":124,"), "{}", written);
+    let written = report(&["--num", "1", "--", "seq 30"]);
+    assert!(written.contains("\"last_output\":\"11\\n12\\n"), "{}", written);
+    assert!(!written.contains("\"last_output\":\"10\\n"), "{}", written);
+}
+
+#[test]
+fn junit(){
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("junit.xml");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--junit", path.to_str().unwrap(), "--for", "a,<b>", "--", "echo \"$ITEM\"; test $ITEM = a"])
+        .assert();
+    let written = std::fs::read_to_string(&path).unwrap();
+    assert!(written.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuite name=\"echo &quot;$ITEM&quot;; test $ITEM = a\" \
+                                 tests=\"2\" failures=\"1\" errors=\"0\" time=\""), "{}", written);
+    assert!(written.contains("  <testcase name=\"iteration 0: a\" classname=\"loop\" time=\""), "{}", written);
+    assert!(written.contains("  <testcase name=\"iteration 1: &lt;b&gt;\" classname=\"loop\" time=\""), "{}", written);
+    assert!(written.contains("\">\n    <failure message=\"exit code 1\">&lt;b&gt;\n</failure>\n  </testcase>\n</testsuite>\n"), "{}", written);
+    Command::cargo_bin("loop").unwrap()
+        .args(["--junit", path.to_str().unwrap(), "--num", "1", "--timeout", "10ms", "--", "sleep 1"])
+        .assert();
+    let written = std::fs::read_to_string(&path).unwrap();
+    assert!(written.contains("<failure message=\"killed: ran longer than 10ms\">"), "{}", written);
+}
+
+#[test]
+fn tap(){
+    test_stdout(vec!["--tap", "--for", "a,b#c", "--", "echo out $ITEM; test $ITEM = a"],
+                "# out a\nok 1 - iteration 0: a\n# out b#c\nnot ok 2 - iteration 1: b\\#c\n# exit code 1\n1..2\n");
+    test_stdout(vec!["--tap", "--num", "2", "--timeout", "10ms", "--", "sleep 1"],
+                "not ok 1 - iteration 0\n# killed: ran longer than 10ms\nnot ok 2 - iteration 1\n# killed: ran longer than 10ms\n1..2\n");
+}
+
+#[test]
+fn gha(){
+    test_stdout(vec!["--gha", "--summary", "--for", "a,b", "--", "echo out $ITEM; test $ITEM = a"],
+                "::group::iteration 0: a\nout a\n::endgroup::\n\
+                 ::group::iteration 1: b\nout b\n::endgroup::\n\
+                 ::error::Iteration 1 failed: exit code 1\n\
+                 ::group::Summary\nTotal runs:\t2\nSuccesses:\t1\nFailures:\t1 (1)\n::endgroup::\n");
+    test_stdout(vec!["--gha", "--num", "1", "--timeout", "10ms", "--", "sleep 1"],
+                "::group::iteration 0\n::endgroup::\n::error::Iteration 0 failed: killed: ran longer than 10ms\n");
+}
+
+#[test]
+fn sink(){
+    test_stdout(vec!["--num", "5", "--sink", "null", "--until-contains", "2", "--summary", "--", "echo $COUNT"],
+                "Total runs:\t3\nSuccesses:\t3\nFailures:\t0\n");
+    test_stdout(vec!["--num", "2", "--sink", "null", "--only-last", "--", "echo $COUNT"], "");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--sink", "file", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn max_lines_per_sec(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--max-lines-per-sec", "3", "--", "seq 5"])
+        .assert().success()
+        .stdout("1\n2\n3\n")
+        .stderr("[2 line(s) suppressed]\n[5 line(s) suppressed]\n");
+    // Conditions still see the dropped lines
+    Command::cargo_bin("loop").unwrap()
+        .args(["--max-lines-per-sec", "1", "--until-contains", "3", "--", "seq 3"])
+        .assert().success()
+        .stdout("1\n");
+}
+
+#[test]
+fn max_output(){
+    // The output is cut before the character crossing the limit.
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--max-output", "10", "--summary", "--", "echo ünïcödé; yes"])
+        .assert().success()
+        .stdout("ünïcöd\n\
+                 Total runs:\t1\n\
+                 Successes:\t0\n\
+                 Failures:\t1 (99)\n")
+        .stderr(predicates::str::starts_with("Killed iteration 0: output of"));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--max-output", "1K", "--", "echo $COUNT"])
+        .assert().success()
+        .stdout("0\n1\n");
+}
+
+#[test]
+fn cpu_time_limit(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--cpu-time-limit", "200ms", "--summary", "--",
+               "sleep 1; echo idle; while :; do :; done"])
+        .assert().success()
+        .stdout("idle\n\
+                 Total runs:\t1\n\
+                 Successes:\t0\n\
+                 Failures:\t1 (99)\n")
+        .stderr(predicates::str::starts_with("Killed iteration 0: CPU time"));
+}
+
+#[test]
+fn timeout(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for=0,1,0", "--timeout", "300ms", "--summary", "--", "sleep $ITEM; echo $COUNT"])
+        .assert().success()
+        .stdout("0\n\
+                 2\n\
+                 Total runs:\t3\n\
+                 Successes:\t2\n\
+                 Failures:\t1 (99)\n")
+        .stderr(predicates::str::starts_with("Killed iteration 1: ran longer than 300ms"));
+}
+
+#[test]
+fn kill_after(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--timeout", "200ms", "--kill-after", "2s", "--", "trap 'echo cleanup; exit 3' TERM; sleep 5 & wait"])
+        .timeout(std::time::Duration::from_secs(2))
+        .assert().success()
+        .stdout("cleanup\n");
+    // Commands ignoring SIGTERM are killed after the grace period.
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--timeout", "200ms", "--kill-after", "200ms", "--summary", "--", "trap '' TERM; sleep 5"])
+        .timeout(std::time::Duration::from_secs(2))
+        .assert().success()
+        .stdout("Total runs:\t1\n\
+                 Successes:\t0\n\
+                 Failures:\t1 (99)\n");
+}
+
+#[test]
+fn heartbeat_file(){
+    let dir = tempfile::tempdir().unwrap();
+    let heartbeat = dir.path().join("heartbeat");
+    test_stdout(
+        vec!["--num", "1", "--heartbeat-file", heartbeat.to_str().unwrap(), "--", "false"],
+        "");
+    assert!(!heartbeat.exists());
+    test_stdout(
+        vec!["--num", "1", "--heartbeat-file", heartbeat.to_str().unwrap(), "--", "true"],
+        "");
+    assert!(std::fs::read_to_string(&heartbeat).unwrap().trim().parse::<u64>().is_ok());
+}
+
+#[test]
+fn config_stdin(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--config-stdin", "--num", "1"])
+        .write_stdin(r#"{"num": 3, "for": ["a", "b"], "command": "echo $ITEM"}"#)
+        .assert().success()
+        .stdout("a\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--config-stdin"])
+        .write_stdin("num = 2\ncommand = [\"echo\", \"$COUNT\"]\n")
+        .assert().success()
+        .stdout("0\n1\n");
+}
+
+#[test]
+fn print_config(){
+    test_stdout(
+        vec!["--print-config", "--num", "2", "--for", "a,b", "--", "echo", "it's $ITEM"],
+        "# loop --num=2 --count-by=1 --offset=0 --every=1us --for=a,b --on-low-space=pause --errors=text -- echo 'it'\\''s $ITEM'\n\
+         num = 2\n\
+         count-by = 1\n\
+         offset = 0\n\
+         every = \"1us\"\n\
+         for = \"a,b\"\n\
+         on-low-space = \"pause\"\n\
+         errors = \"text\"\n\
+         command = [\"echo\", \"it's $ITEM\"]\n");
+}
+
+#[test]
+fn config_options(){
+    // Every option of --help can be given in a config.
+    let help = Command::cargo_bin("loop").unwrap().arg("--help").output().unwrap();
+    let help = String::from_utf8(help.stdout).unwrap();
+    let longs = help.split(|c: char| c.is_whitespace() || c == ',')
+        .filter_map(|word| word.strip_prefix("--"))
+        .map(|word| word.split(['=', '<']).next().unwrap())
+        .filter(|long| !long.is_empty() && !["help", "version"].contains(long) && !long.starts_with("config"));
+    for long in longs {
+        let output = Command::cargo_bin("loop").unwrap()
+            .args(["--config-stdin", "--print-config"])
+            .write_stdin(format!("{} = \"1\"\n", long))
+            .output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("unknown option"), "{}: {}", long, stderr);
+    }
+}
+
+#[test]
+fn env_defaults(){
+    Command::cargo_bin("loop").unwrap()
+        .env("LOOP_NUM", "3")
+        .env("LOOP_SUMMARY", "1")
+        .args(["--num", "1", "--", "echo $COUNT"])
+        .assert().success()
+        .stdout("0\n\
+                 Total runs:\t1\n\
+                 Successes:\t1\n\
+                 Failures:\t0\n");
+}
+
+#[test]
+fn env_defaults_exported(){
+    // Variables loop exports to its commands are no defaults.
+    Command::cargo_bin("loop").unwrap()
+        .env("LOOP_TAG", "outer")
+        .args(["--num", "1", "--env-template", "TAGGED=x{tag}", "--", "echo \"$LOOP_TAG $TAGGED\""])
+        .assert().success()
+        .stdout(" x\n");
+    Command::cargo_bin("loop").unwrap()
+        .env("LOOP_TAG", "outer")
+        .args(["--num", "1", "--tag", "inner", "--", "echo $LOOP_TAG"])
+        .assert().success()
+        .stdout("inner\n");
+}
+
+#[test]
+fn errors_json(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-error=abc", "--errors", "json", "--", "true"])
+        .assert().code(65)
+        .stderr("{\"error\": \"condition_parse\", \"message\": \"Invalid condition: 'abc' is not an exit code\", \"exit_code\": 65}\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for-duration", "0s", "--error-duration", "--", "true"])
+        .assert().code(124)
+        .stderr("Timed out after 0s\n");
+}
+
+#[test]
+fn until_time_zones(){
+    // Half an hour from now in UTC is in the past on a clock at UTC+2.
+    let output = std::process::Command::new("date").args(["-u", "-d", "+30 min", "+%Y-%m-%d %H:%M:%S"]).output().unwrap();
+    let utc = String::from_utf8(output.stdout).unwrap().trim().to_owned();
+    let until = |args: &[&str]| {
+        let mut command = Command::cargo_bin("loop").unwrap();
+        command.env("TZ", "UTC-2").args(["--num", "2", "--until-time"]).args(args).args(["--", "echo x"]);
+        command
+    };
+    until(&[&utc, "--utc"]).assert().success().stdout("x\nx\n");
+    until(&[&utc]).assert().success().stdout("")
+        .stderr("The --until-time has passed already, not running the command\n");
+    until(&[&format!("{}Z", utc.replace(' ', "T"))]).assert().success().stdout("x\nx\n");
+    until(&[&format!("{}+00:00", utc)]).assert().success().stdout("x\nx\n");
+    until(&[&format!("{}+0100", utc)]).assert().success().stdout("");
+    until(&["2024-06-01"]).assert().success().stdout("");
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    until(&[&format!("@{}", now + 3600)]).assert().success().stdout("x\nx\n");
+    until(&[&(now + 3600).to_string()]).assert().success().stdout("x\nx\n");
+    until(&[&format!("{}.5", now - 3600)]).assert().success().stdout("");
+    until(&["@soon"]).assert().code(64);
+    until(&["2024-13-01"]).assert().code(64);
+}
+
+#[test]
+fn until_local_time(){
+    test_stdout(vec!["--num", "2", "--until-time", "tomorrow 00:00", "--", "echo x"], "x\nx\n");
+    test_stdout(vec!["--num", "2", "--until-time", "today 00:00", "--", "echo x"], "");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-time", "2001-01-01 00:00:00", "--", "echo x"])
+        .assert().success().stdout("")
+        .stderr("The --until-time has passed already, not running the command\n");
+    test_stdout(vec!["--num", "2", "--until-time", "+1h", "--", "echo x"], "x\nx\n");
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--every", "200ms", "--until-time", "+500ms", "--", "echo x"], "x\nx\nx\n");
+    assert!(start.elapsed() < std::time::Duration::from_millis(1500));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-time", "someday 25:00", "--", "echo x"])
+        .assert().code(64);
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-time", "+soon", "--", "echo x"])
+        .assert().code(64);
+}
+
+#[test]
+fn after_resume(){
+    let start = std::time::Instant::now();
+    test_stdout(vec!["--num", "3", "--every", "100ms", "--after-resume", "skip", "--", "echo $COUNT"], "0\n1\n2\n");
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+    Command::cargo_bin("loop").unwrap()
+        .args(["--after-resume", "later", "--", "echo x"])
+        .assert().code(64);
+}
+
+#[test]
+fn respect_retry_after(){
+    let start = std::time::Instant::now();
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--every", "10s", "--respect-retry-after", "--",
+               "printf 'HTTP/1.1 503\\r\\nRetry-After: Wed, 21 Oct 2015 07:28:00 GMT\\r\\n'"])
+        .assert().success();
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    let start = std::time::Instant::now();
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "2", "--respect-retry-after", "--", "echo 'retry-after: 1'"])
+        .assert().success();
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn until_dns(){
+    test_stdout(vec!["--num", "3", "--until-dns", "localhost", "--", "echo x"], "x\n");
+    test_stdout(vec!["--num", "3", "--until-dns", "localhost=127.0.0.1", "--", "echo x"], "x\n");
+    test_stdout(vec!["--num", "3", "--until-dns", "localhost=192.0.2.1", "--", "echo x"], "x\nx\nx\n");
+}
+
+#[test]
+fn until_cmd(){
+    // The probe succeeds once the workload wrote its third line.
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("log");
+    let probe = format!("test $(wc -l < {}) -ge 3 && echo probed", file.display());
+    let workload = format!("echo $COUNT; echo $COUNT >> {}", file.display());
+    test_stdout(vec!["--num", "10", "--until-cmd", &probe, "--", &workload], "0\n1\n2\n");
+    test_stdout(vec!["--num", "3", "--until-cmd", "exit 3", "--", "echo x"], "x\nx\nx\n");
+    test_stdout(vec!["--num", "3", "--until-cmd", "exit 3", "--until-cmd-exit", "3", "--", "echo x"], "x\n");
+}
+
+#[test]
+fn tag(){
+    test_stdout(
+        vec!["--for", "a,b", "--tag", "{item}-{count}", "--env-template", "OUT={tag}.log", "--", "echo $LOOP_TAG $OUT"],
+        "a-0 a-0.log\n\
+         b-1 b-1.log\n");
+}
+
+#[test]
+fn checkpoint_every(){
+    test_stdout(
+        vec!["--num", "5", "--checkpoint-every", "2", "echo checkpoint", "--", "echo $COUNT"],
+        "0\n1\ncheckpoint\n2\n3\ncheckpoint\n4\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "5", "--checkpoint-every", "2", "false", "--", "echo $COUNT"])
+        .assert().code(69)
+        .stdout("0\n1\n")
+        .stderr("Checkpoint 'false' failed with exit code 1\n");
+}
+
+#[test]
+fn reload_config(){
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("loop.toml");
+    std::fs::write(&config, "every = \"100ms\"\n").unwrap();
+    let running = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--config", config.to_str().unwrap(), "--", "echo x"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(250));
+    std::fs::write(&config, "until-contains = \"x\"\nsummary = true\n").unwrap();
+    std::process::Command::new("kill").args(["-HUP", &running.id().to_string()]).status().unwrap();
+    let output = running.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Total runs"));
+    assert_eq!(String::from_utf8_lossy(&output.stderr),
+               format!("Not applying the change of 'summary' in {}, which needs a restart\nReloaded {0}\n", config.display()));
+}
+
+#[test]
+fn reload_config_keeps_until_time(){
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("loop.toml");
+    std::fs::write(&config, "every = \"100ms\"\nuntil-time = \"+1s\"\n").unwrap();
+    let start = std::time::Instant::now();
+    let running = std::process::Command::new(env!("CARGO_BIN_EXE_loop"))
+        .args(["--config", config.to_str().unwrap(), "--", "true"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn().unwrap();
+    for _ in 0..2 {
+        std::thread::sleep(std::time::Duration::from_millis(400));
+        std::process::Command::new("kill").args(["-HUP", &running.id().to_string()]).status().unwrap();
+    }
+    let output = running.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(start.elapsed() < std::time::Duration::from_millis(1600));
+}
+
+#[test]
+fn arm_after(){
+    test_stdout(vec!["--arm-after", "2", "--until-contains", "x", "--", "echo x"], "x\nx\nx\n");
+    test_stdout(vec!["--arm-after", "1h", "--num", "2", "--until-success", "--", "echo x"], "x\nx\n");
+}
+
+#[test]
+fn confirm(){
+    test_stdout(
+        vec!["--confirm", "3", "--until-contains", "1x", "--", "echo $(( COUNT > 0 && COUNT != 2 ))x"],
+        "0x\n1x\n0x\n1x\n1x\n1x\n");
+}
+
+#[test]
+fn require_all(){
+    let args = ["--num", "6", "--until-contains", "3", "--until-success", "--", "echo $COUNT; test $COUNT -ge 3 -a $COUNT -ne 3"];
+    test_stdout([&args[..2], &["--require-any"], &args[2..]].concat(), "0\n1\n2\n3\n");
+    test_stdout([&args[..2], &["--require-all"], &args[2..]].concat(), "0\n1\n2\n3\n4\n5\n");
+    test_stdout(vec!["--require-all", "--until-contains", "2", "--until-match", "^[0-9]$", "--until-fail", "--",
+                     "echo $COUNT; test $COUNT -ne 2 -a $COUNT -ne 12"],
+                "0\n1\n2\n");
+    test_stdout(vec!["--require-all", "--require-any", "--num", "3", "--until-contains", "1", "--until-success", "--", "echo $COUNT"],
+                "0\n");
+}
+
+#[test]
+fn enter_exit(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--enter", ">5", "--exit", "<=2", "--", "echo $(( COUNT < 7 ? COUNT : 2 ))"])
+        .assert().success()
+        .stdout("0\n1\n2\n3\n4\n5\n6\n")
+        .stderr("Threshold reached: 6 after 0s beyond the threshold\n");
+    // The alarm stays pending between the thresholds.
+    Command::cargo_bin("loop").unwrap()
+        .args(["--every", "100ms", "--enter", ">5 for 250ms", "--exit", "<2", "--", "echo $(( COUNT % 3 + 5 ))"])
+        .assert().success()
+        .stdout("5\n6\n7\n5\n6\n");
+}
+
+#[test]
+fn merge(){
+    test_stdout(
+        vec!["--for", "a,b", "--merge", "tr a-z A-Z; ls $LOOP_OUTPUT_DIR", "--", "echo $ITEM"],
+        "a\nb\nA\nB\n000000\n000001\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--merge", "exit 3", "--", "true"])
+        .assert().code(3);
+}
+
+#[test]
+fn pin_cpus_and_nice(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--num", "1", "--pin-cpus", "--", "grep Cpus_allowed_list /proc/self/status | cut -f2"])
+        .assert().success()
+        .stdout(predicates::str::is_match("^[0-9]+\n$").unwrap());
+    test_stdout(vec!["--num", "1", "--nice", "5", "--", "cut -d' ' -f19 /proc/self/stat"], "5\n");
+    // A niceness of -1 is no failure, where raising the priority is allowed.
+    let uid = std::process::Command::new("id").arg("-u").output().unwrap().stdout;
+    if uid == b"0\n" {
+        test_stdout(vec!["--num", "1", "--nice=-1", "--", "cut -d' ' -f19 /proc/self/stat"], "-1\n");
+    }
+}
+
+#[test]
+fn queue_max(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["--queue-max", "2", "--", "echo $ITEM"])
+        .write_stdin("1\n2\n3\n4\n5\n")
+        .assert().success()
+        .stdout("1\n2\n3\n4\n5\n");
+    // The newest item survives, whatever was dropped.
+    Command::cargo_bin("loop").unwrap()
+        .args(["--queue-max", "1", "--on-full", "drop-oldest", "--", "sleep 0.2; echo $ITEM"])
+        .write_stdin("1\n2\n3\n4\n5\n")
+        .assert().success()
+        .stdout(predicates::str::ends_with("5\n"));
+}
+
+#[test]
+fn for_range(){
+    test_stdout(vec!["--for-range", "1..10:3", "--", "echo $ITEM"], "1\n4\n7\n10\n");
+    test_stdout(vec!["--for-range", "3..1", "--", "echo $ITEM"], "3\n2\n1\n");
+    test_stdout(vec!["--for-range", "0..1:0.5", "--", "echo $ITEM"], "0.0\n0.5\n1.0\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for-range", "1..5:0", "--", "echo $ITEM"])
+        .assert().code(64);
+}
+
+#[test]
+fn bisect(){
+    test_stdout(vec!["--bisect", "1..100", "--", "echo $ITEM $COUNT; test $ITEM -lt 37"],
+                "51 51\n26 26\n39 39\n33 33\n36 36\n38 38\n37 37\nFirst failure at 37\n");
+    test_stdout(vec!["--bisect", "1..3", "--", "true"], "No failure in 1..3\n");
+    test_stdout(vec!["--bisect", "1..100", "--num", "1", "--", "false"], "First failure in 1..51\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--bisect", "5..1", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn bisect_git(){
+    let dir = tempfile::tempdir().unwrap();
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=loop", "-c", "user.email=loop@example.com"])
+            .args(args)
+            .current_dir(dir.path())
+            .status().unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    for n in 1..=10 {
+        std::fs::write(dir.path().join("n"), n.to_string()).unwrap();
+        git(&["add", "n"]);
+        git(&["commit", "-q", "-m", &format!("Commit {}", n)]);
+    }
+    // Commit 5 can't be tested, 7 is the first bad one
+    Command::cargo_bin("loop").unwrap()
+        .current_dir(dir.path())
+        .args(["--bisect-git", "HEAD~9..HEAD", "--", "n=$(cat n); test $n -ne 5 || exit 125; test $n -lt 7 >/dev/null"])
+        .assert().success()
+        .stdout(predicates::str::is_match("^First bad commit: [0-9a-f]{40} Commit 7\n$").unwrap());
+    assert_eq!(std::fs::read_to_string(dir.path().join("n")).unwrap(), "10");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--bisect-git", "HEAD", "--", "true"])
+        .assert().code(64);
+}
+
+#[test]
+fn for_cmd(){
+    test_stdout(vec!["--for-cmd", "printf 'a\\nb\\n'", "--", "echo $ITEM"], "a\nb\n");
+    test_stdout(vec!["--for-cmd", "echo x; echo y", "--restart-for-cmd", "--num", "5", "--", "echo $ITEM"],
+                "x\ny\nx\ny\nx\n");
+}
+
+#[test]
+fn null(){
+    Command::cargo_bin("loop").unwrap()
+        .args(["-0", "--", "echo \"[$ITEM]\""])
+        .write_stdin("a b\0two\nlines\0")
+        .assert().success()
+        .stdout("[a b]\n[two\nlines]\n");
+    test_stdout(vec!["--null", "--for-cmd", "printf 'x\\0y\\0'", "--", "echo $ITEM"], "x\ny\n");
+}
+
+#[test]
+fn for_csv(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("data.csv");
+    std::fs::write(&file, "host,first port\r\nalpha,80\r\n\"beta, \"\"b\"\"\",443\r\n").unwrap();
+    test_stdout(
+        vec!["--for-csv", file.to_str().unwrap(), "--csv-header", "--", "echo \"$ITEM_host:$ITEM_first_port $ITEM2\""],
+        "alpha:80 80\n\
+         beta, \"b\":443 443\n");
+    test_stdout(
+        vec!["--for-csv", file.to_str().unwrap(), "--", "echo \"$ITEM1|$ITEM\""],
+        "host|host,first port\n\
+         alpha|alpha,80\n\
+         beta, \"b\"|beta, \"b\",443\n");
+}
+
+#[test]
+fn for_jsonl(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("events.jsonl");
+    std::fs::write(&file, "{\"id\": 1, \"user\": {\"name\": \"ann\"}, \"tags\": [\"a\"]}\n\n{\"id\": 2, \"user\": null}\n").unwrap();
+    test_stdout(
+        vec!["--for-jsonl", file.to_str().unwrap(), "--field", "id", "--field", "user.name", "--field", "tags",
+             "--", "echo \"$ITEM_id:$ITEM_user_name:$ITEM_tags\""],
+        "1:ann:[\"a\"]\n\
+         2::\n");
+    std::fs::write(&file, "{\"id\": 1}\n{\"id\": \n").unwrap();
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for-jsonl", file.to_str().unwrap(), "--", "echo $ITEM"])
+        .assert().failure()
+        .stderr(predicates::str::contains("line 2"));
+}
+
+#[test]
+fn until_file(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("out");
+    let path = file.to_str().unwrap();
+    test_stdout(vec!["--until-file-lines", path, ">=", "3", "--", &format!("echo x | tee -a {}", path)], "x\nx\nx\n");
+    std::fs::remove_file(&file).unwrap();
+    test_stdout(vec!["--until-file-size", path, ">=", "4", "--", &format!("printf ab >> {}; echo x", path)], "x\nx\n");
+    std::fs::remove_file(&file).unwrap();
+    test_stdout(
+        vec!["--for", "a,b,c,d", "--until-file-size", path, "stalls", "1K", "--",
+             &format!("test $ITEM = c || printf ab >> {}; echo $ITEM", path)],
+        "a\nb\nc\n");
+    Command::cargo_bin("loop").unwrap()
+        .args(["--until-file-size", path, "~", "1K", "--", "true"])
+        .assert().code(65);
+}
+
+#[test]
+fn process(){
+    let dir = tempfile::tempdir().unwrap();
+    let program = dir.path().join("loop-test-sleeper");
+    std::fs::copy("/bin/sleep", &program).unwrap();
+    let mut sleeper = std::process::Command::new(&program).arg("30").spawn().unwrap();
+    test_stdout(vec!["--num", "3", "--while-process", "loop-test-sleeper", "--", "echo x"], "x\nx\nx\n");
+    test_stdout(vec!["--num", "3", "--until-process", "loop-test-sl.*", "--", "echo x"], "x\n");
+    sleeper.kill().unwrap();
+    sleeper.wait().unwrap();
+    test_stdout(vec!["--num", "3", "--while-process", "loop-test-sleeper", "--", "echo x"], "x\n");
+    test_stdout(vec!["--num", "3", "--until-process", "loop-test-sleeper", "--", "echo x"], "x\nx\nx\n");
+}
+
+#[test]
+fn ping(){
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap().to_string();
+    test_stdout(vec!["--until-ping", "127.0.0.1", "--", "echo x"], "x\n");
+    test_stdout(vec!["--num", "2", "--while-ping", &host, "--", "echo x"], "x\nx\n");
+    // .invalid names never resolve
+    test_stdout(vec!["--num", "2", "--while-ping", "host.invalid", "--", "echo x"], "x\n");
+}
+
+#[test]
+fn for_file(){
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("items");
+    std::fs::write(&file, "a\nb\n").unwrap();
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for-file", file.to_str().unwrap(), "--", "echo $ITEM; cat"])
+        .write_stdin("hello\n")
+        .assert().success()
+        .stdout("a\nhello\nb\n");
+}
+
+#[test]
+fn consume(){
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // A NATS server delivering two messages and collecting their acks.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("nats://{}/jobs", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"INFO {}\r\n").unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while !line.starts_with("SUB") {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        stream.write_all(b"MSG jobs 1 ack.1 3\r\nfoo\r\nMSG jobs 1 ack.2 3\r\nbar\r\n").unwrap();
+        let mut acks = vec![0; 2 * "PUB ack.1 4\r\n+ACK\r\n".len()];
+        reader.read_exact(&mut acks).unwrap();
+        String::from_utf8(acks).unwrap()
+    });
+    Command::cargo_bin("loop").unwrap()
+        .args(["--consume", &url, "--ack-on-success", "--", "echo $ITEM; cat; echo"])
+        .assert().success()
+        .stdout("foo\nfoo\nbar\nbar\n");
+    assert_eq!(server.join().unwrap(), "PUB ack.1 4\r\n+ACK\r\nPUB ack.2 4\r\n+ACK\r\n");
+}
+
+#[test]
+fn jobs(){
+    // Five iterations of 300ms in parallel, printed in order.
+    let start = std::time::Instant::now();
+    test_stdout(
+        vec!["--jobs", "5", "--for=a,b,c,d,e", "--", "sleep 0.3; echo $ITEM"],
+        "a\nb\nc\nd\ne\n");
+    assert!(start.elapsed() < std::time::Duration::from_millis(1200));
+    // Until conditions stop all workers.
+    test_stdout(
+        vec!["-j", "2", "--for=false,false,true,false,false,false", "--until-success", "--summary", "--", "$ITEM"],
+        "Total runs:\t3\n\
+         Successes:\t1\n\
+         Failures:\t2 (1, 1)\n");
+}
+
+#[test]
+fn for_redis(){
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    fn read_command(reader: &mut BufReader<TcpStream>) -> Vec<String> {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let args: usize = line.trim_start_matches('*').trim().parse().unwrap();
+        (0..args * 2).map(|_| {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+            line.trim().to_owned()
+        }).skip(1).step_by(2).collect()
+    }
+
+    // A Redis server with two items in its list, recording dead letters.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let server = std::thread::spawn(move || {
+        let (mut list, _) = listener.accept().unwrap();
+        let (mut dead_letters, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(list.try_clone().unwrap());
+        for item in ["a", "b"] {
+            assert_eq!(read_command(&mut reader), ["BRPOP", "jobs", "0"]);
+            write!(list, "*2\r\n$4\r\njobs\r\n$1\r\n{}\r\n", item).unwrap();
+        }
+        read_command(&mut reader);
+        drop(list);
+        let mut reader = BufReader::new(dead_letters.try_clone().unwrap());
+        let command = read_command(&mut reader);
+        dead_letters.write_all(b":1\r\n").unwrap();
+        command
+    });
+    Command::cargo_bin("loop").unwrap()
+        .args(["--for-redis", "list:jobs", "--redis-address", &address, "--dead-letter", "failed", "--",
+               "echo $ITEM; test $ITEM = a"])
+        .assert().success()
+        .stdout("a\nb\n");
+    assert_eq!(server.join().unwrap(), ["LPUSH", "failed", "b"]);
+}
+
+#[test]
+fn mqtt(){
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    // An MQTT broker publishing the given messages once subscribed to.
+    fn broker(messages: Vec<(&'static str, &'static str, u64)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let read_packet = |stream: &mut TcpStream| {
+                let mut header = [0; 2];
+                stream.read_exact(&mut header).unwrap();
+                let mut body = vec![0; header[1] as usize];
+                stream.read_exact(&mut body).unwrap();
+            };
+            read_packet(&mut stream);
+            stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            read_packet(&mut stream);
+            stream.write_all(&[0x90, 4, 0, 1, 0, 0]).unwrap();
+            for (topic, payload, delay) in messages {
+                std::thread::sleep(std::time::Duration::from_millis(delay));
+                let mut packet = vec![0x30, (2 + topic.len() + payload.len()) as u8, 0, topic.len() as u8];
+                packet.extend(topic.bytes().chain(payload.bytes()));
+                stream.write_all(&packet).unwrap();
+            }
+        });
+        address
+    }
+
+    let address = broker(vec![("sensors/door", "open", 0), ("sensors/window", "closed", 0)]);
+    test_stdout(
+        vec!["--mqtt-broker", &address, "--mqtt-trigger", "sensors/+", "--", "echo $ITEM"],
+        "open\nclosed\n");
+
+    let address = broker(vec![("state", "busy", 0), ("state", "idle", 300)]);
+    Command::cargo_bin("loop").unwrap()
+        .args(["--mqtt-broker", &address, "--until-mqtt", "state", "idle", "--every", "100ms", "--", "echo polling"])
+        .timeout(std::time::Duration::from_secs(5))
+        .assert().success()
+        .stdout(predicates::str::starts_with("polling\n"));
+}
+
+#[test]
+fn clipboard(){
+    use std::os::unix::fs::PermissionsExt;
+
+    // An xclip keeping the clipboard in a file next to it.
+    let dir = tempfile::tempdir().unwrap();
+    let xclip = dir.path().join("xclip");
+    std::fs::write(&xclip, "#!/bin/sh\n\
+        case \"$3\" in\n\
+        -out) cat \"$(dirname \"$0\")/clipboard\" ;;\n\
+        -in) cat > \"$(dirname \"$0\")/clipboard\" ;;\n\
+        esac\n").unwrap();
+    std::fs::set_permissions(&xclip, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(dir.path().join("clipboard"), "one\ntwo").unwrap();
+    let path = format!("{}:{}", dir.path().display(), std::env::var("PATH").unwrap());
+    Command::cargo_bin("loop").unwrap()
+        .env("PATH", path)
+        .env_remove("WAYLAND_DISPLAY")
+        .args(["--for-clipboard", "--copy-last", "--", "echo $ITEM | tr a-z A-Z"])
+        .assert().success()
+        .stdout("ONE\nTWO\n");
+    assert_eq!(std::fs::read_to_string(dir.path().join("clipboard")).unwrap(), "TWO\n");
+}
+
+#[test]
+fn on_dbus(){
+    use std::os::unix::fs::PermissionsExt;
+
+    // A dbus-monitor reporting its own NameAcquired, then two signals.
+    let dir = tempfile::tempdir().unwrap();
+    let monitor = dir.path().join("dbus-monitor");
+    std::fs::write(&monitor, "#!/bin/sh\n\
+        echo \"$@\" > \"$(dirname \"$0\")/args\"\n\
+        echo 'signal time=1 sender=org.freedesktop.DBus -> destination=:1.5 serial=2 path=/org/freedesktop/DBus; interface=org.freedesktop.DBus; member=NameAcquired'\n\
+        echo '   string \":1.5\"'\n\
+        echo 'signal time=2 sender=:1.3 -> destination=(null destination) serial=7 path=/org/freedesktop/login1; interface=org.freedesktop.login1.Manager; member=PrepareForSleep'\n\
+        echo '   boolean true'\n\
+        sleep 0.2\n\
+        echo 'signal time=3 sender=:1.3 -> destination=(null destination) serial=8 path=/org/freedesktop/login1; interface=org.freedesktop.login1.Manager; member=PrepareForSleep'\n\
+        echo '   boolean false'\n").unwrap();
+    std::fs::set_permissions(&monitor, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", dir.path().display(), std::env::var("PATH").unwrap());
+    Command::cargo_bin("loop").unwrap()
+        .env("PATH", path)
+        .args(["--on-dbus", "member='PrepareForSleep'", "--", "echo $ITEM"])
+        .assert().success()
+        .stdout("org.freedesktop.login1.Manager.PrepareForSleep true\n\
+                 org.freedesktop.login1.Manager.PrepareForSleep false\n");
+    assert_eq!(std::fs::read_to_string(dir.path().join("args")).unwrap(), "--system member='PrepareForSleep'\n");
 }